@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use libchip8cpu::CPU;
+
+const INSTRUCTIONS_PER_ROM: u64 = 5_000_000;
+const TICKS_PER_FRAME: u64 = 30;
+
+/// Instructions per second achieved running `rom`, ticking the timers as the desktop frontend does.
+fn run(rom: &[u8], instruction_cache: bool) -> f64 {
+    let mut cpu = CPU::new();
+    cpu.set_instruction_cache(instruction_cache);
+    cpu.load(rom).unwrap();
+    let start = Instant::now();
+    let mut executed = 0;
+    while executed < INSTRUCTIONS_PER_ROM {
+        if cpu.tick().is_err() {
+            break;
+        }
+        executed += 1;
+        if executed % TICKS_PER_FRAME == 0 {
+            cpu.tick_timers();
+        }
+    }
+    executed as f64 / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut roms: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    roms.sort();
+
+    println!(
+        "{:<12} {:>14} {:>14} {:>8}",
+        "rom", "plain ips", "cached ips", "speedup"
+    );
+    let mut totals = (0.0, 0.0);
+    for path in &roms {
+        let rom = fs::read(path).unwrap();
+        let plain = run(&rom, false);
+        let cached = run(&rom, true);
+        totals = (totals.0 + plain, totals.1 + cached);
+        println!(
+            "{:<12} {:>14.0} {:>14.0} {:>7.2}x",
+            path.file_name().unwrap().to_string_lossy(),
+            plain,
+            cached,
+            cached / plain
+        );
+    }
+    let count = roms.len() as f64;
+    println!(
+        "{:<12} {:>14.0} {:>14.0} {:>7.2}x",
+        "mean",
+        totals.0 / count,
+        totals.1 / count,
+        totals.1 / totals.0
+    );
+}
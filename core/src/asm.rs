@@ -0,0 +1,679 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::START_ADDR;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into a ROM image meant to be loaded at the program start address.
+/// `include` directives are rejected, use `assemble_with_includes` to resolve them.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_includes(source, "<input>", |path| {
+        Err(format!("cannot include {:?} without a file resolver", path))
+    })
+}
+
+/// Assembles `source`, calling `read` with the path of every `include` directive to get the
+/// included source. `name` is used to identify `source` in errors.
+pub fn assemble_with_includes<F>(source: &str, name: &str, mut read: F) -> Result<Vec<u8>, AsmError>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut program = Program {
+        statements: Vec::new(),
+        symbols: HashMap::new(),
+        address: START_ADDR as u32,
+    };
+    program.parse(source, name, &mut read, 0)?;
+    program.emit()
+}
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+fn tokenize(line: &str, location: &Location) -> Result<Vec<Token>, AsmError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < chars.len() {
+        let c = chars[idx];
+        let column = idx + 1;
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            let start = idx;
+            while idx < chars.len()
+                && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_' || chars[idx] == '.')
+            {
+                idx += 1;
+            }
+            tokens.push(Token {
+                kind: TokenKind::Word(chars[start..idx].iter().collect()),
+                column,
+            });
+        } else if c == '"' {
+            let start = idx + 1;
+            idx = start;
+            while idx < chars.len() && chars[idx] != '"' {
+                idx += 1;
+            }
+            if idx == chars.len() {
+                let location = Location {
+                    column,
+                    ..location.clone()
+                };
+                return Err(location.error("unterminated string"));
+            }
+            tokens.push(Token {
+                kind: TokenKind::Str(chars[start..idx].iter().collect()),
+                column,
+            });
+            idx += 1;
+        } else if ",:=[]+-".contains(c) {
+            tokens.push(Token {
+                kind: TokenKind::Punct(c),
+                column,
+            });
+            idx += 1;
+        } else {
+            let location = Location {
+                column,
+                ..location.clone()
+            };
+            return Err(location.error(format!("unexpected character {:?}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(u32),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(bool, Term)>,
+    location: Location,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<(Operand, Location)>,
+        location: Location,
+    },
+    Data {
+        width: usize,
+        values: Vec<Expr>,
+    },
+}
+
+struct Program {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    address: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(u32),
+    Constant(Expr),
+}
+
+fn parse_number(word: &str) -> Option<u32> {
+    let lower = word.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn parse_register(word: &str) -> Option<u8> {
+    let mut chars = word.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn is_identifier(word: &str) -> bool {
+    word.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+}
+
+fn parse_expr(tokens: &[Token], location: &Location) -> Result<Expr, AsmError> {
+    let at = |column: usize| Location {
+        column,
+        ..location.clone()
+    };
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut expect_term = true;
+    for token in tokens {
+        match (&token.kind, expect_term) {
+            (TokenKind::Punct('-'), true) => negative = !negative,
+            (TokenKind::Punct('+'), true) => {}
+            (TokenKind::Word(word), true) => {
+                let term = if let Some(number) = parse_number(word) {
+                    Term::Number(number)
+                } else if is_identifier(word) {
+                    Term::Symbol(word.clone())
+                } else {
+                    return Err(at(token.column).error(format!("invalid number {:?}", word)));
+                };
+                terms.push((negative, term));
+                negative = false;
+                expect_term = false;
+            }
+            (TokenKind::Punct('+'), false) => expect_term = true,
+            (TokenKind::Punct('-'), false) => {
+                negative = true;
+                expect_term = true;
+            }
+            _ => return Err(at(token.column).error("unexpected token in expression")),
+        }
+    }
+    if expect_term {
+        return Err(location.error("expected a value"));
+    }
+    Ok(Expr {
+        terms,
+        location: location.clone(),
+    })
+}
+
+fn parse_operand(tokens: &[Token], location: &Location) -> Result<Operand, AsmError> {
+    let words: Vec<String> = tokens
+        .iter()
+        .map(|token| match &token.kind {
+            TokenKind::Word(word) => word.to_ascii_uppercase(),
+            TokenKind::Punct(c) => c.to_string(),
+            TokenKind::Str(_) => String::new(),
+        })
+        .collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let operand = match words.as_slice() {
+        ["I"] => Operand::I,
+        ["[", "I", "]"] => Operand::IndirectI,
+        ["DT"] => Operand::Dt,
+        ["ST"] => Operand::St,
+        ["K"] => Operand::K,
+        ["F"] => Operand::F,
+        ["HF"] => Operand::Hf,
+        ["B"] => Operand::B,
+        ["R"] => Operand::R,
+        [register] if parse_register(register).is_some() => {
+            Operand::Register(parse_register(register).unwrap_or_default())
+        }
+        ["LONG", ..] => Operand::Long(parse_expr(&tokens[1..], location)?),
+        _ => Operand::Value(parse_expr(tokens, location)?),
+    };
+    Ok(operand)
+}
+
+fn instruction_size(mnemonic: &str, operands: &[(Operand, Location)]) -> u32 {
+    match (mnemonic, operands) {
+        ("LD", [(Operand::I, _), (Operand::Long(_), _)]) => 4,
+        _ => 2,
+    }
+}
+
+impl Program {
+    fn parse<F>(
+        &mut self,
+        source: &str,
+        name: &str,
+        read: &mut F,
+        depth: usize,
+    ) -> Result<(), AsmError>
+    where
+        F: FnMut(&str) -> Result<String, String>,
+    {
+        for (idx, line) in source.lines().enumerate() {
+            let mut location = Location {
+                file: name.to_string(),
+                line: idx + 1,
+                column: 1,
+            };
+            let mut tokens = tokenize(line, &location)?;
+
+            if let [Token {
+                kind: TokenKind::Word(label),
+                column,
+            }, Token {
+                kind: TokenKind::Punct(':'),
+                ..
+            }, ..] = tokens.as_slice()
+            {
+                location.column = *column;
+                self.define(label, Symbol::Label(self.address), &location)?;
+                tokens.drain(..2);
+            }
+            let first = match tokens.first() {
+                Some(first) => first.clone(),
+                None => continue,
+            };
+            location.column = first.column;
+            let word = match &first.kind {
+                TokenKind::Word(word) => word.clone(),
+                _ => return Err(location.error("expected a mnemonic or label")),
+            };
+            if let Some(Token {
+                kind: TokenKind::Punct('='),
+                column,
+            }) = tokens.get(1)
+            {
+                let value_location = Location {
+                    column: column + 1,
+                    ..location.clone()
+                };
+                let expr = parse_expr(&tokens[2..], &value_location)?;
+                self.define(&word, Symbol::Constant(expr), &location)?;
+                continue;
+            }
+
+            let mnemonic = word.to_ascii_uppercase();
+            let operands = split_operands(&tokens[1..]);
+            match mnemonic.as_str() {
+                "INCLUDE" => {
+                    let path = match tokens.get(1).map(|token| &token.kind) {
+                        Some(TokenKind::Str(path)) if tokens.len() == 2 => path.clone(),
+                        _ => return Err(location.error("include expects a quoted path")),
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location.error("includes are nested too deeply"));
+                    }
+                    let included = read(&path).map_err(|message| location.error(message))?;
+                    self.parse(&included, &path, read, depth + 1)?;
+                }
+                "DB" | "DW" => {
+                    let width = if mnemonic == "DB" { 1 } else { 2 };
+                    let mut values = Vec::new();
+                    for operand in operands {
+                        let operand_location = operand_location(operand, &location);
+                        values.push(parse_expr(operand, &operand_location)?);
+                    }
+                    if values.is_empty() {
+                        return Err(location.error(format!("{} expects values", mnemonic)));
+                    }
+                    self.address += (width * values.len()) as u32;
+                    self.statements.push(Statement::Data { width, values });
+                }
+                _ => {
+                    let mut parsed = Vec::new();
+                    for operand in operands {
+                        let operand_location = operand_location(operand, &location);
+                        parsed.push((parse_operand(operand, &operand_location)?, operand_location));
+                    }
+                    self.address += instruction_size(&mnemonic, &parsed);
+                    self.statements.push(Statement::Instruction {
+                        mnemonic,
+                        operands: parsed,
+                        location,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<(), AsmError> {
+        if !is_identifier(name) || parse_register(name).is_some() {
+            return Err(location.error(format!("{:?} is not a valid name", name)));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(location.error(format!("{:?} is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError> {
+        if depth > self.symbols.len() {
+            return Err(expr.location.error("constant refers to itself"));
+        }
+        let mut total = 0i64;
+        for (negative, term) in &expr.terms {
+            let value = match term {
+                Term::Number(number) => *number as i64,
+                Term::Symbol(name) => match self.symbols.get(name) {
+                    Some(Symbol::Label(address)) => *address as i64,
+                    Some(Symbol::Constant(expr)) => self.evaluate(expr, depth + 1)?,
+                    None => {
+                        return Err(expr.location.error(format!("undefined symbol {:?}", name)))
+                    }
+                },
+            };
+            total += if *negative { -value } else { value };
+        }
+        Ok(total)
+    }
+
+    fn value(&self, expr: &Expr, bits: u32) -> Result<u16, AsmError> {
+        let value = self.evaluate(expr, 0)?;
+        let max = (1i64 << bits) - 1;
+        if !(0..=max).contains(&value) {
+            return Err(expr
+                .location
+                .error(format!("value {} does not fit in {} bits", value, bits)));
+        }
+        Ok(value as u16)
+    }
+
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for statement in &self.statements {
+            match statement {
+                Statement::Data { width, values } => {
+                    for expr in values {
+                        if *width == 1 {
+                            rom.push(self.value(expr, 8)? as u8);
+                        } else {
+                            rom.extend_from_slice(&self.value(expr, 16)?.to_be_bytes());
+                        }
+                    }
+                }
+                Statement::Instruction {
+                    mnemonic,
+                    operands,
+                    location,
+                } => {
+                    for word in self.encode(mnemonic, operands, location)? {
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn encode(
+        &self,
+        mnemonic: &str,
+        operands: &[(Operand, Location)],
+        location: &Location,
+    ) -> Result<Vec<u16>, AsmError> {
+        use Operand::*;
+        let ops: Vec<&Operand> = operands.iter().map(|(operand, _)| operand).collect();
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let word = match (mnemonic, ops.as_slice()) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCD", [Value(n)]) => 0x00C0 | self.value(n, 4)?,
+            ("SCU", [Value(n)]) => 0x00D0 | self.value(n, 4)?,
+            ("SCR", []) => 0x00FB,
+            ("SCL", []) => 0x00FC,
+            ("EXIT", []) => 0x00FD,
+            ("LOW", []) => 0x00FE,
+            ("HIGH", []) => 0x00FF,
+            ("SYS", [Value(nnn)]) => self.value(nnn, 12)?,
+            ("JP", [Value(nnn)]) => 0x1000 | self.value(nnn, 12)?,
+            ("JP", [Register(0), Value(nnn)]) => 0xB000 | self.value(nnn, 12)?,
+            ("CALL", [Value(nnn)]) => 0x2000 | self.value(nnn, 12)?,
+            ("SE", [Register(x), Value(kk)]) => xy(0x3000, *x, 0, 0) | self.value(kk, 8)?,
+            ("SNE", [Register(x), Value(kk)]) => xy(0x4000, *x, 0, 0) | self.value(kk, 8)?,
+            ("SE", [Register(x), Register(y)]) => xy(0x5000, *x, *y, 0),
+            ("SAVE", [Register(x), Register(y)]) => xy(0x5000, *x, *y, 2),
+            ("LOAD", [Register(x), Register(y)]) => xy(0x5000, *x, *y, 3),
+            ("LD", [Register(x), Value(kk)]) => xy(0x6000, *x, 0, 0) | self.value(kk, 8)?,
+            ("ADD", [Register(x), Value(kk)]) => xy(0x7000, *x, 0, 0) | self.value(kk, 8)?,
+            ("LD", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 0),
+            ("OR", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 1),
+            ("AND", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 2),
+            ("XOR", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 3),
+            ("ADD", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 4),
+            ("SUB", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 5),
+            ("SHR", [Register(x)]) => xy(0x8000, *x, *x, 6),
+            ("SHR", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 6),
+            ("SUBN", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 7),
+            ("SHL", [Register(x)]) => xy(0x8000, *x, *x, 0xE),
+            ("SHL", [Register(x), Register(y)]) => xy(0x8000, *x, *y, 0xE),
+            ("SNE", [Register(x), Register(y)]) => xy(0x9000, *x, *y, 0),
+            ("LD", [I, Value(nnn)]) => 0xA000 | self.value(nnn, 12)?,
+            ("LD", [I, Long(nnnn)]) => return Ok(vec![0xF000, self.value(nnnn, 16)?]),
+            ("RND", [Register(x), Value(kk)]) => xy(0xC000, *x, 0, 0) | self.value(kk, 8)?,
+            ("DRW", [Register(x), Register(y), Value(n)]) => {
+                xy(0xD000, *x, *y, 0) | self.value(n, 4)?
+            }
+            ("SKP", [Register(x)]) => xy(0xE09E, *x, 0, 0),
+            ("SKNP", [Register(x)]) => xy(0xE0A1, *x, 0, 0),
+            // XO-CHIP only selects planes 1 and 2, but the encoding has room for a 4-bit mask.
+            ("PLANE", [Value(n)]) => 0xF001 | self.value(n, 4)? << 8,
+            ("AUDIO", []) => 0xF002,
+            ("LD", [Register(x), Dt]) => xy(0xF007, *x, 0, 0),
+            ("LD", [Register(x), K]) => xy(0xF00A, *x, 0, 0),
+            ("LD", [Dt, Register(x)]) => xy(0xF015, *x, 0, 0),
+            ("LD", [St, Register(x)]) => xy(0xF018, *x, 0, 0),
+            ("ADD", [I, Register(x)]) => xy(0xF01E, *x, 0, 0),
+            ("LD", [F, Register(x)]) => xy(0xF029, *x, 0, 0),
+            ("LD", [Hf, Register(x)]) => xy(0xF030, *x, 0, 0),
+            ("LD", [B, Register(x)]) => xy(0xF033, *x, 0, 0),
+            ("PITCH", [Register(x)]) => xy(0xF03A, *x, 0, 0),
+            ("LD", [IndirectI, Register(x)]) => xy(0xF055, *x, 0, 0),
+            ("LD", [Register(x), IndirectI]) => xy(0xF065, *x, 0, 0),
+            ("LD", [R, Register(x)]) => xy(0xF075, *x, 0, 0),
+            ("LD", [Register(x), R]) => xy(0xF085, *x, 0, 0),
+            _ => {
+                return Err(location.error(format!(
+                    "invalid instruction {} with {} operand(s)",
+                    mnemonic,
+                    operands.len()
+                )))
+            }
+        };
+        Ok(vec![word])
+    }
+}
+
+fn split_operands(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    tokens
+        .split(|token| token.kind == TokenKind::Punct(','))
+        .collect()
+}
+
+fn operand_location(operand: &[Token], location: &Location) -> Location {
+    Location {
+        column: operand
+            .first()
+            .map_or(location.column, |token| token.column),
+        ..location.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).expect_err("source should not assemble")
+    }
+
+    #[test]
+    fn labels_resolve_forward_and_backward() {
+        let source = "start: JP end\n    CALL start\nend: JP start\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x12, 0x04, 0x22, 0x00, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn long_loads_count_four_bytes_for_later_labels() {
+        let source = "LD I, LONG data\nJP data\ndata: DB 1\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0xF0, 0x00, 0x02, 0x06, 0x12, 0x06, 0x01]
+        );
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let source = "SPEED = 3\nTOP = SPEED + 0x10 - 1\nLD V1, TOP\nADD V1, -SPEED + 4\n";
+        assert_eq!(assemble(source).unwrap(), [0x61, 0x12, 0x71, 0x01]);
+    }
+
+    #[test]
+    fn data_directives() {
+        let source = "DB 1, 0xFF, 0b101\nDW 0x1234, label\nlabel:\n";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x01, 0xFF, 0x05, 0x12, 0x34, 0x02, 0x07]
+        );
+        assert_eq!(error("DB").message, "DB expects values");
+        assert_eq!(error("DB 256").message, "value 256 does not fit in 8 bits");
+    }
+
+    #[test]
+    fn includes_are_read_through_the_resolver() {
+        let rom =
+            assemble_with_includes("include \"sprites.s\"\nLD I, smile\n", "main.s", |path| {
+                assert_eq!(path, "sprites.s");
+                Ok("smile: DB 0x66, 0x00, 0x81, 0x7E\n".to_string())
+            })
+            .unwrap();
+        assert_eq!(rom, [0x66, 0x00, 0x81, 0x7E, 0xA2, 0x00]);
+
+        let err = assemble_with_includes("CLS\ninclude \"missing.s\"\n", "main.s", |path| {
+            Err(format!("no such file {path}"))
+        })
+        .unwrap_err();
+        assert_eq!((err.file.as_str(), err.line, err.column), ("main.s", 2, 1));
+        assert_eq!(err.message, "no such file missing.s");
+        assert!(assemble("include \"other.s\"").is_err());
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let err = error("CLS\n    LD V1, missing\n");
+        assert_eq!((err.line, err.column), (2, 12));
+        assert_eq!(err.message, "undefined symbol \"missing\"");
+        assert_eq!(
+            err.to_string(),
+            "<input>:2:12: undefined symbol \"missing\""
+        );
+
+        let err = error("  JP 0x1000");
+        assert_eq!((err.line, err.column), (1, 6));
+
+        let err = error("a: CLS\na: RET");
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(err.message, "\"a\" is defined more than once");
+
+        let err = error("LD V1, V2, V3");
+        assert_eq!((err.line, err.column), (1, 1));
+
+        let err = error("DB \"open");
+        assert_eq!(
+            (err.line, err.column, err.message.as_str()),
+            (1, 4, "unterminated string")
+        );
+
+        let err = error("LOOP = LOOP\nLD V0, LOOP");
+        assert_eq!(err.message, "constant refers to itself");
+    }
+
+    fn assert_round_trip(rom: &[u8]) {
+        let source = disassemble(rom).to_source();
+        let reassembled = assemble(&source).unwrap_or_else(|err| panic!("{err}\n{source}"));
+        assert_eq!(reassembled, rom, "\n{source}");
+    }
+
+    #[test]
+    fn disassembly_reassembles_byte_for_byte() {
+        let roms = concat!(env!("CARGO_MANIFEST_DIR"), "/../roms");
+        let mut count = 0;
+        for dir in [roms.to_string(), format!("{roms}/tests")] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    assert_round_trip(&std::fs::read(&path).unwrap());
+                    count += 1;
+                }
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn plane_masks_up_to_15_round_trip() {
+        assert_eq!(assemble("PLANE 3").unwrap(), [0xF3, 0x01]);
+        assert_round_trip(&[0xF4, 0x01, 0xFF, 0x01, 0x12, 0x00]);
+        assert_eq!(error("PLANE 16").message, "value 16 does not fit in 4 bits");
+    }
+}
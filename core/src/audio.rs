@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sine,
+}
+
+impl Waveform {
+    /// The full-scale sample at `phase`, a fraction of one period in `0.0..1.0`. Square edges
+    /// are smoothed with PolyBLEP for a tone advancing `dt` per sample, so they do not alias.
+    fn sample(self, phase: f64, dt: f64) -> f64 {
+        match self {
+            Waveform::Square => {
+                let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+                naive + poly_blep(phase, dt) - poly_blep((phase + 0.5) % 1.0, dt)
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * std::f64::consts::TAU).sin(),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Triangle => write!(f, "triangle"),
+            Waveform::Sine => write!(f, "sine"),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform {name}")),
+        }
+    }
+}
+
+/// The buzzer sound frontends play while the sound timer is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tone {
+    /// Pitch in Hz.
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// Amplitude from 0.0 (silent) to 1.0.
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Longest stretch of audio kept waiting for a frontend to pull it, in timer frames.
+const MAX_QUEUED_FRAMES: usize = 15;
+/// Time the volume takes to fade in or out when the buzzer switches, so it does not click.
+const RAMP_SECONDS: f32 = 0.002;
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_SIZE * 8) as f64;
+
+/// What the buzzer did during one timer frame.
+#[derive(Debug, Clone, Copy)]
+struct AudioFrame {
+    samples: usize,
+    on: bool,
+    /// The XO-CHIP pattern and its playback rate in bits per second, when one is playing.
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>,
+}
+
+/// Turns the buzzer state of each timer frame into PCM samples.
+///
+/// The machine records one `AudioFrame` per timer tick and the samples are only rendered when a
+/// frontend pulls them, so the waveform stays continuous across frames and across underruns,
+/// which play as silence.
+pub(crate) struct AudioSynth {
+    sample_rate: u32,
+    pub(crate) tone: Tone,
+    frames: VecDeque<AudioFrame>,
+    /// Leftover sample fractions, in sixtieths of a sample.
+    sample_carry: u32,
+    /// Position in the current period of the tone, or in bits of the pattern.
+    phase: f64,
+    gain: f32,
+}
+
+impl AudioSynth {
+    pub(crate) fn new() -> Self {
+        Self {
+            sample_rate: 0,
+            tone: Tone::default(),
+            frames: VecDeque::new(),
+            sample_carry: 0,
+            phase: 0.0,
+            gain: 0.0,
+        }
+    }
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), CpuError> {
+        if sample_rate != 0 && sample_rate < TIMER_FREQUENCY {
+            return Err(CpuError::InvalidSampleRate { rate: sample_rate });
+        }
+        self.sample_rate = sample_rate;
+        self.clear();
+        Ok(())
+    }
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+        self.sample_carry = 0;
+    }
+    pub(crate) fn queued_samples(&self) -> usize {
+        self.frames.iter().map(|frame| frame.samples).sum()
+    }
+    /// Records the buzzer state for the timer frame that just ended.
+    pub(crate) fn push_frame(&mut self, cpu: &CPU) {
+        if self.sample_rate == 0 {
+            return;
+        }
+        let total = self.sample_carry + self.sample_rate;
+        self.sample_carry = total % TIMER_FREQUENCY;
+        let samples = (total / TIMER_FREQUENCY) as usize;
+        if samples == 0 {
+            return;
+        }
+        let pattern = cpu.audio_pattern();
+        let pattern = (cpu.variant().is_xo_chip() && pattern.iter().any(|bits| *bits != 0))
+            .then(|| (*pattern, cpu.audio_playback_rate()));
+        self.frames.push_back(AudioFrame {
+            samples,
+            on: cpu.is_sound_active(),
+            pattern,
+        });
+        if self.frames.len() > MAX_QUEUED_FRAMES {
+            self.frames.pop_front();
+        }
+    }
+    pub(crate) fn fill(&mut self, out: &mut [f32]) {
+        for sample in out {
+            let frame = match self.frames.front_mut() {
+                Some(frame) => {
+                    let current = *frame;
+                    frame.samples -= 1;
+                    if frame.samples == 0 {
+                        self.frames.pop_front();
+                    }
+                    current
+                }
+                None => AudioFrame {
+                    samples: 0,
+                    on: false,
+                    pattern: None,
+                },
+            };
+            *sample = self.render(&frame);
+        }
+    }
+    fn render(&mut self, frame: &AudioFrame) -> f32 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+        let sample_rate = self.sample_rate as f64;
+        let target = if frame.on { self.tone.volume } else { 0.0 };
+        let step = 1.0 / (RAMP_SECONDS * self.sample_rate as f32);
+        self.gain = if self.gain < target {
+            (self.gain + step).min(target)
+        } else {
+            (self.gain - step).max(target)
+        };
+        let value = match &frame.pattern {
+            Some((bits, rate)) => {
+                let dt = *rate as f64 / sample_rate;
+                let value = pattern_sample(bits, self.phase, dt);
+                self.phase = (self.phase + dt) % PATTERN_BITS;
+                value
+            }
+            None => {
+                let dt = self.tone.frequency as f64 / sample_rate;
+                self.phase %= 1.0;
+                let value = self.tone.waveform.sample(self.phase, dt);
+                self.phase = (self.phase + dt) % 1.0;
+                value
+            }
+        };
+        value as f32 * self.gain
+    }
+}
+
+/// Correction for an upward unit step at phase 0, for a waveform advancing `dt` per sample.
+fn poly_blep(phase: f64, dt: f64) -> f64 {
+    if dt <= 0.0 || dt >= 0.5 {
+        0.0
+    } else if phase < dt {
+        let t = phase / dt;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - dt {
+        let t = (phase - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+fn pattern_bit(bits: &[u8; AUDIO_PATTERN_SIZE], idx: i64) -> f64 {
+    let idx = idx.rem_euclid(PATTERN_BITS as i64) as usize;
+    if bits[idx / 8] & (0x80 >> (idx % 8)) != 0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// The pattern at bit position `phase`, with PolyBLEP smoothing on the edges between bits. When
+/// more than half a bit passes per sample the bits covered are averaged instead.
+fn pattern_sample(bits: &[u8; AUDIO_PATTERN_SIZE], phase: f64, dt: f64) -> f64 {
+    let idx = phase.floor() as i64;
+    if dt >= 0.5 {
+        let count = dt.ceil() as i64;
+        let sum: f64 = (idx..idx + count).map(|idx| pattern_bit(bits, idx)).sum();
+        return sum / count as f64;
+    }
+    let t = phase - phase.floor();
+    let current = pattern_bit(bits, idx);
+    let mut value = current;
+    if t < dt {
+        value += (current - pattern_bit(bits, idx - 1)) / 2.0 * poly_blep(t, dt);
+    } else if t > 1.0 - dt {
+        value += (pattern_bit(bits, idx + 1) - current) / 2.0 * poly_blep(t, dt);
+    }
+    value
+}
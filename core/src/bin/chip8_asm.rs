@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use libchip8cpu::asm::assemble_with_includes;
+
+fn usage() -> ! {
+    eprintln!("usage: chip8-asm <source> [-o <output>]");
+    exit(2);
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut source_path = None;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())))
+            }
+            "-h" | "--help" => usage(),
+            _ if source_path.is_none() => source_path = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    let source_path = source_path.unwrap_or_else(|| usage());
+    let output_path = output_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+
+    let source = match fs::read_to_string(&source_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Unable to read {}: {err}", source_path.display());
+            exit(1);
+        }
+    };
+    let base = source_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let rom = assemble_with_includes(&source, &source_path.display().to_string(), |path| {
+        fs::read_to_string(base.join(path))
+            .map_err(|err| format!("unable to include {path}: {err}"))
+    });
+    match rom {
+        Ok(rom) => {
+            if let Err(err) = fs::write(&output_path, rom) {
+                eprintln!("Unable to write {}: {err}", output_path.display());
+                exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+
+use libchip8cpu::disasm::Instruction;
+use libchip8cpu::*;
+
+const DEFAULT_FRAMES: u64 = 600;
+const DEFAULT_HOLD: u64 = 5;
+const PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 102, 0], [102, 34, 0]];
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: chip8-headless <rom> [--frames <n>] [--variant chip8|schip|xochip] [--ips <n>]
+                      [--seed <n>] [--key <frame>:<key>[:<hold>]]...
+                      [--png <file>] [--pbm <file>] [--json <file>|-]"
+    );
+    exit(2);
+}
+
+/// A key held down for `hold` frames starting at `frame`.
+struct ScriptedKey {
+    frame: u64,
+    key: u8,
+    hold: u64,
+}
+
+struct Options {
+    rom_path: PathBuf,
+    frames: u64,
+    variant: Option<Variant>,
+    instructions_per_second: u32,
+    seed: u64,
+    keys: Vec<ScriptedKey>,
+    png: Option<PathBuf>,
+    pbm: Option<PathBuf>,
+    json: Option<PathBuf>,
+}
+
+/// Why the run ended.
+enum Outcome {
+    Frames,
+    SelfJump,
+    Exit,
+    Error(CpuError),
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Frames => "frames",
+            Outcome::SelfJump => "self-jump",
+            Outcome::Exit => "exit",
+            Outcome::Error(_) => "error",
+        }
+    }
+}
+
+pub fn main() {
+    let options = parse_options(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        usage()
+    });
+    let rom = fs::read(&options.rom_path).unwrap_or_else(|err| {
+        eprintln!("Unable to read {}: {err}", options.rom_path.display());
+        exit(1);
+    });
+    let variant = options.variant.unwrap_or_else(|| {
+        match options.rom_path.extension().and_then(|ext| ext.to_str()) {
+            Some("sc8") => Variant::SuperChip,
+            Some("xo8") => Variant::XoChip,
+            _ => Variant::Chip8,
+        }
+    });
+    let mut cpu = CPU::new_with_variant(variant, variant.quirks()).with_seed(options.seed);
+    if let Err(err) = cpu.load(&rom) {
+        eprintln!("Unable to load rom: {err}");
+        exit(1);
+    }
+    let mut machine = Machine::new(cpu, options.instructions_per_second);
+
+    let mut frame = 0;
+    let mut outcome = Outcome::Frames;
+    while frame < options.frames {
+        let timestamp = Duration::from_secs(frame) / TIMER_FREQUENCY;
+        for key in &options.keys {
+            let pressed = if frame == key.frame {
+                true
+            } else if frame == key.frame + key.hold {
+                false
+            } else {
+                continue;
+            };
+            // Keys were range-checked while parsing.
+            let _ = machine.push_key_event(KeyEvent::new(key.key, pressed, timestamp));
+        }
+        let result = machine.run_frame();
+        frame += 1;
+        if let Err(err) = result {
+            outcome = Outcome::Error(err);
+            break;
+        }
+        if machine.cpu().is_halted() {
+            outcome = Outcome::Exit;
+            break;
+        }
+        let pc = machine.cpu().pc();
+        if machine.debugger().current_instruction(machine.cpu()) == Some(Instruction::Jp(pc)) {
+            outcome = Outcome::SelfJump;
+            break;
+        }
+    }
+
+    let mut failed = false;
+    let display = machine.cpu().get_display();
+    if let Some(path) = &options.png {
+        failed |= report_write(path, fs::write(path, encode_png(&display)));
+    }
+    if let Some(path) = &options.pbm {
+        failed |= report_write(path, fs::write(path, encode_pbm(&display)));
+    }
+    if let Some(path) = &options.json {
+        let json = register_dump(machine.cpu(), frame, &outcome);
+        let result = if path == Path::new("-") {
+            io::stdout().write_all(json.as_bytes())
+        } else {
+            fs::write(path, json)
+        };
+        failed |= report_write(path, result);
+    }
+    if let Outcome::Error(err) = &outcome {
+        eprintln!("Halted after {frame} frames: {err}");
+        exit(1);
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        variant: None,
+        instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+        seed: DEFAULT_SEED,
+        keys: Vec::new(),
+        png: None,
+        pbm: None,
+        json: None,
+    };
+    let mut rom_path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} expects a value"));
+        match arg.as_str() {
+            "--frames" => options.frames = parse_number(&value()?)?,
+            "--ips" => options.instructions_per_second = parse_number(&value()?)?,
+            "--seed" => options.seed = parse_number(&value()?)?,
+            "--variant" => {
+                options.variant = Some(match value()?.as_str() {
+                    "chip8" => Variant::Chip8,
+                    "schip" => Variant::SuperChip,
+                    "xochip" => Variant::XoChip,
+                    other => return Err(format!("unknown variant {other}")),
+                })
+            }
+            "--key" => options.keys.push(parse_key(&value()?)?),
+            "--png" => options.png = Some(PathBuf::from(value()?)),
+            "--pbm" => options.pbm = Some(PathBuf::from(value()?)),
+            "--json" => options.json = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => usage(),
+            _ if rom_path.is_none() && !arg.starts_with("--") => {
+                rom_path = Some(PathBuf::from(arg))
+            }
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+    options.rom_path = rom_path.ok_or("missing rom")?;
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid number {value}"))
+}
+
+/// Parses `frame:key[:hold]`, with the key as a hex digit and a hold of one frame or more.
+fn parse_key(value: &str) -> Result<ScriptedKey, String> {
+    let mut parts = value.split(':');
+    let frame = parse_number(parts.next().unwrap_or_default())?;
+    let key = parts
+        .next()
+        .and_then(|key| u8::from_str_radix(key, 16).ok())
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid key in {value}"))?;
+    let hold = match parts.next() {
+        Some(hold) => parse_number(hold)?,
+        None => DEFAULT_HOLD,
+    };
+    if hold == 0 {
+        return Err(format!("hold in {value} must be at least one frame"));
+    }
+    if parts.next().is_some() {
+        return Err(format!("invalid key script {value}"));
+    }
+    Ok(ScriptedKey { frame, key, hold })
+}
+
+fn report_write(path: &Path, result: io::Result<()>) -> bool {
+    if let Err(err) = &result {
+        eprintln!("Unable to write {}: {err}", path.display());
+    }
+    result.is_err()
+}
+
+fn register_dump(cpu: &CPU, frames: u64, outcome: &Outcome) -> String {
+    let list = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<_>>().join(", ");
+    let mut json = String::from("{\n");
+    let _ = writeln!(json, "  \"frames\": {frames},");
+    let _ = writeln!(json, "  \"halt\": \"{}\",", outcome.name());
+    if let Outcome::Error(err) = outcome {
+        let message = err.to_string().replace('\\', "\\\\").replace('"', "\\\"");
+        let _ = writeln!(json, "  \"error\": \"{message}\",");
+    }
+    let _ = writeln!(json, "  \"pc\": {},", cpu.pc());
+    let _ = writeln!(json, "  \"i\": {},", cpu.i());
+    let _ = writeln!(
+        json,
+        "  \"v\": [{}],",
+        list(&mut cpu.registers().iter().map(|v| v.to_string()))
+    );
+    let _ = writeln!(json, "  \"sp\": {},", cpu.sp());
+    let _ = writeln!(
+        json,
+        "  \"stack\": [{}],",
+        list(&mut cpu.stack().iter().map(|v| v.to_string()))
+    );
+    let _ = writeln!(json, "  \"delay_timer\": {},", cpu.delay_timer());
+    let _ = writeln!(json, "  \"sound_timer\": {}", cpu.sound_timer());
+    json.push_str("}\n");
+    json
+}
+
+/// Plain PBM, one character per pixel, with every lit plane drawn black.
+fn encode_pbm(display: &Display) -> Vec<u8> {
+    let mut pbm = format!("P1\n{} {}\n", display.width, display.height);
+    for row in display.pixels.chunks(display.width as usize) {
+        let line: Vec<&str> = row
+            .iter()
+            .map(|pixel| if *pixel != 0 { "1" } else { "0" })
+            .collect();
+        pbm.push_str(&line.join(" "));
+        pbm.push('\n');
+    }
+    pbm.into_bytes()
+}
+
+/// Indexed PNG in the desktop palette. The image data is stored uncompressed; the frame buffer
+/// is at most 8 KiB, so compression is not worth a dependency.
+fn encode_png(display: &Display) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(display.pixels.len() + display.height as usize);
+    for row in display.pixels.chunks(display.width as usize) {
+        raw.push(0); // no filter
+        raw.extend(row.iter().map(|pixel| pixel & 0b11));
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(display.width.to_be_bytes());
+    header.extend(display.height.to_be_bytes());
+    header.extend([8, 3, 0, 0, 0]); // 8-bit palette indices, no interlacing
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", PALETTE.as_flattened());
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
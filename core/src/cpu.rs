@@ -1,13 +1,88 @@
-extern crate rand;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::fmt;
+
+use disasm::Instruction;
+
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+mod audio;
+#[cfg(feature = "alloc")]
+mod debugger;
+pub mod disasm;
+mod input;
+#[cfg(feature = "alloc")]
+mod keymap;
+#[cfg(feature = "alloc")]
+mod machine;
+#[cfg(feature = "alloc")]
+mod movie;
+mod quirks;
+#[cfg(feature = "alloc")]
+mod rewind;
+mod rng;
+mod savestate;
+#[cfg(feature = "std")]
+mod trace;
+
+#[cfg(feature = "std")]
+use audio::AudioSynth;
+#[cfg(feature = "std")]
+pub use audio::{Tone, Waveform};
+#[cfg(feature = "alloc")]
+pub use debugger::{Access, Debugger, Register, StopReason, Watchpoint};
+pub use input::KeyEvent;
+#[cfg(feature = "alloc")]
+pub use keymap::{HostKey, KeyMap};
+#[cfg(feature = "alloc")]
+pub use machine::{FrameResult, Machine, DEFAULT_INSTRUCTIONS_PER_SECOND};
+#[cfg(feature = "alloc")]
+pub use movie::{Movie, MoviePlayer, MovieRecorder};
+pub use quirks::Quirks;
+#[cfg(feature = "alloc")]
+pub use rewind::RewindBuffer;
+#[cfg(feature = "rand")]
+pub use rng::EntropyRng;
+pub use rng::{Rng, XorShiftRng, DEFAULT_SEED};
+#[cfg(feature = "std")]
+pub use trace::{TraceFormat, Tracer};
 
 pub const SCREEN_WIDTH: u32 = 0x0040;
 pub const SCREEN_HEIGHT: u32 = 0x0020;
+pub const HIRES_SCREEN_WIDTH: u32 = 0x0080;
+pub const HIRES_SCREEN_HEIGHT: u32 = 0x0040;
+pub const TIMER_FREQUENCY: u32 = 60;
+#[cfg(feature = "schip")]
+const FRAME_BUFFER_WIDTH: u32 = HIRES_SCREEN_WIDTH;
+#[cfg(feature = "schip")]
+const FRAME_BUFFER_HEIGHT: u32 = HIRES_SCREEN_HEIGHT;
+#[cfg(not(feature = "schip"))]
+const FRAME_BUFFER_WIDTH: u32 = SCREEN_WIDTH;
+#[cfg(not(feature = "schip"))]
+const FRAME_BUFFER_HEIGHT: u32 = SCREEN_HEIGHT;
+const FRAME_BUFFER_SIZE: usize = (FRAME_BUFFER_WIDTH * FRAME_BUFFER_HEIGHT) as usize;
 const START_ADDR: u16 = 0x0200;
 const MEMORY_SIZE: usize = 0x1000;
+#[cfg(feature = "xochip")]
+const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+#[cfg(feature = "xochip")]
+const MAX_MEMORY_SIZE: usize = XO_CHIP_MEMORY_SIZE;
+#[cfg(not(feature = "xochip"))]
+const MAX_MEMORY_SIZE: usize = MEMORY_SIZE;
 const NUM_DATA_REGISTERS: usize = 0x10;
 const STACK_LENGTH: usize = 0x10;
 const FONT_SET_SIZE: usize = 80;
 const NUM_KEYS: usize = 16;
+const NUM_RPL_FLAGS: usize = 16;
+const NUM_SUPER_CHIP_RPL_FLAGS: usize = 8;
+const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+const BIG_FONT_ADDR: usize = FONT_SET_SIZE;
+const BIG_FONT_SET_SIZE: usize = 160;
 const FONT_SET: [u8; FONT_SET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -26,8 +101,138 @@ const FONT_SET: [u8; FONT_SET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+const BIG_FONT_SET: [u8; BIG_FONT_SET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// The instruction set a program is written for. SUPER-CHIP and XO-CHIP are only available with
+/// the `schip` and `xochip` features, which the smaller builds can leave out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Variant {
+    Chip8,
+    #[cfg(feature = "schip")]
+    SuperChip,
+    #[cfg(feature = "xochip")]
+    XoChip,
+}
+
+impl Variant {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Variant::Chip8 => Quirks::default(),
+            #[cfg(feature = "schip")]
+            Variant::SuperChip => Quirks::SUPER_CHIP,
+            #[cfg(feature = "xochip")]
+            Variant::XoChip => Quirks::XO_CHIP,
+        }
+    }
+    fn memory_size(self) -> usize {
+        match self {
+            #[cfg(feature = "xochip")]
+            Variant::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+    fn is_xo_chip(self) -> bool {
+        match self {
+            #[cfg(feature = "xochip")]
+            Variant::XoChip => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Display<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// One entry per pixel holding a bitmask of the planes lit at that pixel. Only XO-CHIP
+    /// programs draw to the second plane, so other variants produce 0 or 1.
+    pub pixels: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+    RomTooLarge { len: usize },
+    InvalidKey { value: usize },
+    InvalidSaveState,
+    UnsupportedSaveStateVersion { version: u8 },
+    SaveStateRomMismatch,
+    SaveStateDisplayMismatch { width: u16, height: u16 },
+    InvalidMovie,
+    MovieRomMismatch,
+    Desync { frame: u64 },
+    InvalidSampleRate { rate: u32 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:04x} at {:#05x}", opcode, pc)
+            }
+            CpuError::StackOverflow => write!(f, "stack overflow"),
+            CpuError::StackUnderflow => write!(f, "stack underflow"),
+            CpuError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#06x}", addr)
+            }
+            CpuError::RomTooLarge { len } => {
+                write!(f, "rom of {} bytes does not fit in memory", len)
+            }
+            CpuError::InvalidKey { value } => write!(f, "invalid key {:#x}", value),
+            CpuError::InvalidSaveState => write!(f, "save state is corrupt"),
+            CpuError::UnsupportedSaveStateVersion { version } => {
+                write!(f, "save state version {} is not supported", version)
+            }
+            CpuError::SaveStateRomMismatch => {
+                write!(f, "save state was made with a different rom")
+            }
+            CpuError::SaveStateDisplayMismatch { width, height } => write!(
+                f,
+                "save state has a {}x{} frame buffer, this build has {}x{}",
+                width, height, FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT
+            ),
+            CpuError::InvalidMovie => write!(f, "movie is corrupt"),
+            CpuError::MovieRomMismatch => write!(f, "movie was recorded with a different rom"),
+            CpuError::Desync { frame } => {
+                write!(f, "playback diverged from the recording at frame {}", frame)
+            }
+            CpuError::InvalidSampleRate { rate } => {
+                write!(
+                    f,
+                    "sample rate {} is below one sample per timer frame",
+                    rate
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CpuError {}
+
 pub struct CPU {
-    memory: [u8; MEMORY_SIZE],
+    memory: [u8; MAX_MEMORY_SIZE],
     data_registers: [u8; NUM_DATA_REGISTERS],
     address_register: u16,
     program_counter: u16,
@@ -35,14 +240,50 @@ pub struct CPU {
     stack_pointer: u8,
     delay_timer: u8,
     sound_timer: u8,
-    frame_buffer: [bool; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+    frame_buffer: [u8; FRAME_BUFFER_SIZE],
     pub inputs: [bool; NUM_KEYS],
+    quirks: Quirks,
+    vblank: bool,
+    variant: Variant,
+    hires: bool,
+    halted: bool,
+    rpl_flags: [u8; NUM_RPL_FLAGS],
+    plane_mask: u8,
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
+    rom_hash: u64,
+    /// The image last loaded, which `restart` puts back.
+    #[cfg(feature = "alloc")]
+    rom: alloc::vec::Vec<u8>,
+    #[cfg(feature = "std")]
+    tracer: Option<Tracer>,
+    rng: rng::CpuRng,
+    display_dirty: bool,
+    waiting_for_key: bool,
+    /// Key held down while `FX0A` waits for it to be released.
+    key_wait: Option<u8>,
+    /// Decoded instruction per address, empty when caching is disabled.
+    #[cfg(feature = "alloc")]
+    instruction_cache: alloc::vec::Vec<Option<Instruction>>,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
     pub fn new() -> Self {
-        let mut memory = [0; MEMORY_SIZE];
+        Self::new_with_quirks(Quirks::default())
+    }
+    pub fn new_with_quirks(quirks: Quirks) -> Self {
+        Self::new_with_variant(Variant::Chip8, quirks)
+    }
+    pub fn new_with_variant(variant: Variant, quirks: Quirks) -> Self {
+        let mut memory = [0; MAX_MEMORY_SIZE];
         memory[..FONT_SET_SIZE].copy_from_slice(&FONT_SET);
+        memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT_SET_SIZE].copy_from_slice(&BIG_FONT_SET);
         Self {
             memory,
             data_registers: [0; NUM_DATA_REGISTERS],
@@ -52,15 +293,125 @@ impl CPU {
             stack_pointer: 0x00,
             delay_timer: 0x00,
             sound_timer: 0x00,
-            frame_buffer: [false; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+            frame_buffer: [0; FRAME_BUFFER_SIZE],
             inputs: [false; NUM_KEYS],
+            quirks,
+            vblank: true,
+            variant,
+            hires: false,
+            halted: false,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            plane_mask: 1,
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            rom_hash: savestate::fnv1a(&[]),
+            #[cfg(feature = "alloc")]
+            rom: alloc::vec::Vec::new(),
+            #[cfg(feature = "std")]
+            tracer: None,
+            rng: rng::seeded(DEFAULT_SEED),
+            display_dirty: true,
+            waiting_for_key: false,
+            key_wait: None,
+            #[cfg(feature = "alloc")]
+            instruction_cache: alloc::vec![None; variant.memory_size()],
+        }
+    }
+    /// Makes `CXNN` draw from the default generator seeded with `seed`, so that runs with the
+    /// same seed and input are identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = rng::seeded(seed);
+        self
+    }
+    #[cfg(feature = "alloc")]
+    pub fn set_rng(&mut self, rng: alloc::boxed::Box<dyn Rng>) {
+        self.rng = rng;
+    }
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.set_hires(false);
+        self.flush_instruction_cache();
+    }
+    /// Decoded instructions are cached per address by default, which makes `tick` considerably
+    /// faster. Disabling the cache decodes every instruction as it is fetched. Builds without
+    /// `alloc` never cache.
+    #[cfg(feature = "alloc")]
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.instruction_cache = alloc::vec::Vec::new();
+        if enabled {
+            self.instruction_cache
+                .resize(self.variant.memory_size(), None);
+        }
+    }
+    fn flush_instruction_cache(&mut self) {
+        #[cfg(feature = "alloc")]
+        if !self.instruction_cache.is_empty() {
+            self.instruction_cache.clear();
+            self.instruction_cache
+                .resize(self.variant.memory_size(), None);
         }
     }
-    pub fn get_display(&self) -> &[bool] {
-        &self.frame_buffer
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+    pub fn registers(&self) -> &[u8; NUM_DATA_REGISTERS] {
+        &self.data_registers
+    }
+    pub fn i(&self) -> u16 {
+        self.address_register
+    }
+    pub fn pc(&self) -> u16 {
+        self.program_counter
+    }
+    pub fn sp(&self) -> u8 {
+        self.stack_pointer
+    }
+    /// Return addresses of the subroutines currently being executed, outermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+    /// The memory addressable by the current variant, fonts included.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.variant.memory_size()]
+    }
+    /// Writes one byte of memory, as `FX55` would, e.g. for cheats or patching a running program.
+    pub fn poke(&mut self, addr: u16, value: u8) -> Result<(), CpuError> {
+        self.write_memory(addr as usize, value)
+    }
+    /// Continues execution at `pc`, abandoning any `FX0A` wait in progress.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.program_counter = pc;
+        self.waiting_for_key = false;
+        self.key_wait = None;
+    }
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+    pub fn get_display(&self) -> Display<'_> {
+        let width = self.screen_width();
+        let height = self.screen_height();
+        Display {
+            width: width as u32,
+            height: height as u32,
+            pixels: &self.frame_buffer[..width * height],
+        }
     }
     pub fn reset(&mut self) {
         self.memory[..FONT_SET_SIZE].copy_from_slice(&FONT_SET);
+        self.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT_SET_SIZE]
+            .copy_from_slice(&BIG_FONT_SET);
         self.data_registers = [0; NUM_DATA_REGISTERS];
         self.address_register = 0x0000;
         self.program_counter = START_ADDR;
@@ -68,247 +419,601 @@ impl CPU {
         self.stack_pointer = 0x00;
         self.delay_timer = 0x00;
         self.sound_timer = 0x00;
-        self.frame_buffer = [false; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize];
+        self.frame_buffer = [0; FRAME_BUFFER_SIZE];
         self.inputs = [false; NUM_KEYS];
+        self.vblank = true;
+        self.hires = false;
+        self.halted = false;
+        self.plane_mask = 1;
+        self.audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        self.pitch = DEFAULT_PITCH;
+        self.display_dirty = true;
+        self.waiting_for_key = false;
+        self.key_wait = None;
+        self.flush_instruction_cache();
     }
-    pub fn tick(&mut self) {
-        let opcode = self.fetch();
-        self.execute(opcode);
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
     }
-    pub fn keypress(&mut self, idx: usize, pressed: bool) {
-        self.inputs[idx] = pressed;
+    pub fn pitch(&self) -> u8 {
+        self.pitch
     }
-    pub fn load(&mut self, data: &[u8]) {
+    /// Rate in bits per second at which the XO-CHIP audio pattern is played back.
+    #[cfg(feature = "std")]
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+    #[cfg(feature = "std")]
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+    #[cfg(feature = "std")]
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+    pub fn tick(&mut self) -> Result<(), CpuError> {
+        if self.halted {
+            return Ok(());
+        }
+        #[cfg(feature = "std")]
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(self);
+            let result = self.step();
+            if let Err(err) = &result {
+                tracer.dump(err);
+            }
+            self.tracer = Some(tracer);
+            return result;
+        }
+        self.step()
+    }
+    pub fn keypress(&mut self, idx: usize, pressed: bool) -> Result<(), CpuError> {
+        match self.inputs.get_mut(idx) {
+            Some(input) => {
+                *input = pressed;
+                Ok(())
+            }
+            None => Err(CpuError::InvalidKey { value: idx }),
+        }
+    }
+    pub fn load(&mut self, data: &[u8]) -> Result<(), CpuError> {
         let start = START_ADDR as usize;
         let end = start + data.len();
+        if end > self.variant.memory_size() {
+            return Err(CpuError::RomTooLarge { len: data.len() });
+        }
         self.memory[start..end].copy_from_slice(data);
+        self.rom_hash = savestate::fnv1a(data);
+        #[cfg(feature = "alloc")]
+        {
+            self.rom = data.to_vec();
+        }
+        self.flush_instruction_cache();
+        Ok(())
     }
-    fn push(&mut self, address: u16) {
-        self.stack[self.stack_pointer as usize] = address;
+    /// Resets the CPU and puts memory back the way `load` left it, undoing anything the program
+    /// wrote, including to the persistent `FX75` flags.
+    #[cfg(feature = "alloc")]
+    pub fn restart(&mut self) {
+        self.memory = [0; MAX_MEMORY_SIZE];
+        let start = START_ADDR as usize;
+        self.memory[start..start + self.rom.len()].copy_from_slice(&self.rom);
+        self.rpl_flags = [0; NUM_RPL_FLAGS];
+        self.reset();
+    }
+    fn push(&mut self, address: u16) -> Result<(), CpuError> {
+        let slot = self
+            .stack
+            .get_mut(self.stack_pointer as usize)
+            .ok_or(CpuError::StackOverflow)?;
+        *slot = address;
         self.stack_pointer += 1;
+        Ok(())
     }
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, CpuError> {
+        if self.stack_pointer == 0 {
+            return Err(CpuError::StackUnderflow);
+        }
         self.stack_pointer -= 1;
-        self.stack[self.stack_pointer as usize]
-    }
-    fn fetch(&mut self) -> u16 {
-        let high_byte = self.read_memory(self.program_counter.into()) as u16;
-        self.program_counter += 1;
-        let low_byte = self.read_memory(self.program_counter.into()) as u16;
-        self.program_counter += 1;
-        (high_byte << 8) | low_byte
-    }
-    fn read_memory(&self, address: usize) -> u8 {
-        self.memory[address]
-    }
-    fn execute(&mut self, opcode: u16) {
-        let d1 = ((opcode & 0xF000) >> 12) as u8;
-        let d2 = ((opcode & 0x0F00) >> 8) as u8;
-        let d3 = ((opcode & 0x00F0) >> 4) as u8;
-        let d4 = (opcode & 0x000F) as u8;
-        match (d1, d2, d3, d4) {
-            (0, 0, 0, 0) => return,
-            (0, 0, 0xE, 0) => self.frame_buffer = [false; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
-            (0, 0, 0xE, 0xE) => {
-                self.program_counter = self.pop();
-            }
-            (1, _, _, _) => {
-                self.program_counter = opcode & 0xFFF;
-            }
-            (2, _, _, _) => {
-                let nnn = opcode & 0xFFF;
-                self.push(self.program_counter);
+        Ok(self.stack[self.stack_pointer as usize])
+    }
+    fn step(&mut self) -> Result<(), CpuError> {
+        let instruction = self.fetch()?;
+        self.execute(instruction)
+    }
+    fn fetch(&mut self) -> Result<Instruction, CpuError> {
+        #[cfg(feature = "alloc")]
+        let pc = self.program_counter;
+        #[cfg(feature = "alloc")]
+        if let Some(Some(instruction)) = self.instruction_cache.get(pc as usize) {
+            self.program_counter = pc.wrapping_add(2);
+            return Ok(*instruction);
+        }
+        let high_byte = self.read_memory(self.program_counter.into())? as u16;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let low_byte = self.read_memory(self.program_counter.into())? as u16;
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let opcode = (high_byte << 8) | low_byte;
+        let next = if opcode == 0xF000 && self.variant.is_xo_chip() {
+            let operand = self.program_counter as usize;
+            ((self.read_memory(operand)? as u16) << 8) | self.read_memory(operand + 1)? as u16
+        } else {
+            0
+        };
+        let instruction = Instruction::decode_for(opcode, next, self.variant);
+        #[cfg(feature = "alloc")]
+        if let Some(slot) = self.instruction_cache.get_mut(pc as usize) {
+            *slot = Some(instruction);
+        }
+        Ok(instruction)
+    }
+    fn read_memory(&self, address: usize) -> Result<u8, CpuError> {
+        if address >= self.variant.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds { addr: address });
+        }
+        Ok(self.memory[address])
+    }
+    fn write_memory(&mut self, address: usize, value: u8) -> Result<(), CpuError> {
+        if address >= self.variant.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds { addr: address });
+        }
+        self.memory[address] = value;
+        // Up to four bytes, for `F000 NNNN`, make up the instruction cached at an address.
+        #[cfg(feature = "alloc")]
+        for offset in 0..4 {
+            let start = (address as u16).wrapping_sub(offset) as usize;
+            if let Some(slot) = self.instruction_cache.get_mut(start) {
+                *slot = None;
+            }
+        }
+        Ok(())
+    }
+    /// The error for the instruction just fetched, which this variant does not implement.
+    fn unknown_opcode(&self) -> CpuError {
+        let pc = self.program_counter.wrapping_sub(2);
+        let opcode = u16::from_be_bytes([
+            self.memory[pc as usize],
+            self.memory[pc.wrapping_add(1) as usize],
+        ]);
+        CpuError::UnknownOpcode { pc, opcode }
+    }
+    /// Steps over the next instruction, which is four bytes long if it is an XO-CHIP `F000 NNNN`.
+    fn skip(&mut self) {
+        let pc = self.program_counter as usize;
+        let long = self.variant.is_xo_chip()
+            && self.read_memory(pc).ok() == Some(0xF0)
+            && self.read_memory(pc + 1).ok() == Some(0x00);
+        self.program_counter = self.program_counter.wrapping_add(if long { 4 } else { 2 });
+    }
+    fn input(&self, key: u8) -> Result<bool, CpuError> {
+        self.inputs
+            .get(key as usize)
+            .copied()
+            .ok_or(CpuError::InvalidKey {
+                value: key as usize,
+            })
+    }
+    fn rpl_flag_count(&self) -> usize {
+        if self.variant.is_xo_chip() {
+            NUM_RPL_FLAGS
+        } else {
+            NUM_SUPER_CHIP_RPL_FLAGS
+        }
+    }
+    fn screen_width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH as usize
+        } else {
+            SCREEN_WIDTH as usize
+        }
+    }
+    fn screen_height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT as usize
+        } else {
+            SCREEN_HEIGHT as usize
+        }
+    }
+    /// Whether the display changed since the last call.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn take_display_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.display_dirty)
+    }
+    /// Whether the program is blocked on `FX0A`, so a frontend can idle until a key changes.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+    /// Whether keypad `key` is held as the program sees it. Out of range keys never are.
+    pub fn is_key_pressed(&self, key: usize) -> bool {
+        self.inputs.get(key).copied().unwrap_or(false)
+    }
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.frame_buffer = [0; FRAME_BUFFER_SIZE];
+        self.display_dirty = true;
+    }
+    fn clear_screen(&mut self) {
+        self.display_dirty = true;
+        for pixel in self.frame_buffer.iter_mut() {
+            *pixel &= !self.plane_mask;
+        }
+    }
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.screen_width() as isize;
+        let height = self.screen_height() as isize;
+        let previous = self.frame_buffer;
+        self.display_dirty = true;
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let visible = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                let scrolled = if visible {
+                    previous[(src_x + src_y * width) as usize] & self.plane_mask
+                } else {
+                    0
+                };
+                let pixel = &mut self.frame_buffer[(x + y * width) as usize];
+                *pixel = (*pixel & !self.plane_mask) | scrolled;
+            }
+        }
+    }
+    /// Draws the sprite at `I` to every selected plane, each plane taking the next sprite's
+    /// worth of data. Returns the number of sprite rows that collided with a lit pixel and the
+    /// number of rows clipped off the bottom of the screen.
+    fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        columns: usize,
+        rows: usize,
+    ) -> Result<(u8, u8), CpuError> {
+        let width = self.screen_width();
+        let height = self.screen_height();
+        let x_cord = x % width;
+        let y_cord = y % height;
+        let bytes_per_row = columns / 8;
+        let mut addr = self.address_register as usize;
+        self.display_dirty = true;
+        let mut collided_rows = 0;
+        let mut clipped_rows = 0;
+        for plane in [1u8, 2] {
+            if self.plane_mask & plane == 0 {
+                continue;
+            }
+            for y_line in 0..rows {
+                let y = y_cord + y_line;
+                if self.quirks.clipping && y >= height {
+                    clipped_rows += 1;
+                    addr += bytes_per_row;
+                    continue;
+                }
+                let y = y % height;
+                let mut collided = false;
+                for byte in 0..bytes_per_row {
+                    let pixels = self.read_memory(addr)?;
+                    addr += 1;
+                    for x_line in 0..8 {
+                        if (pixels & (0b1000_0000 >> x_line)) != 0 {
+                            let x = x_cord + byte * 8 + x_line;
+                            if self.quirks.clipping && x >= width {
+                                continue;
+                            }
+                            let idx = (x % width) + width * y;
+                            collided |= self.frame_buffer[idx] & plane != 0;
+                            self.frame_buffer[idx] ^= plane;
+                        }
+                    }
+                }
+                if collided {
+                    collided_rows += 1;
+                }
+            }
+        }
+        Ok((collided_rows, clipped_rows))
+    }
+    fn execute(&mut self, instruction: Instruction) -> Result<(), CpuError> {
+        let super_chip = self.variant != Variant::Chip8;
+        let xo_chip = self.variant.is_xo_chip();
+        match instruction {
+            Instruction::Sys(0) => {}
+            Instruction::Cls => self.clear_screen(),
+            Instruction::Ret => {
+                self.program_counter = self.pop()?;
+            }
+            Instruction::ScrollDown(n) if super_chip => self.scroll(0, n as isize),
+            Instruction::ScrollUp(n) if xo_chip => self.scroll(0, -(n as isize)),
+            Instruction::ScrollRight if super_chip => self.scroll(4, 0),
+            Instruction::ScrollLeft if super_chip => self.scroll(-4, 0),
+            Instruction::Exit if super_chip => self.halted = true,
+            Instruction::Low if super_chip => self.set_hires(false),
+            Instruction::High if super_chip => self.set_hires(true),
+            Instruction::Jp(nnn) => {
+                self.program_counter = nnn;
+            }
+            Instruction::Call(nnn) => {
+                self.push(self.program_counter)?;
                 self.program_counter = nnn;
             }
-            (3, x, k1, k2) => {
-                let kk = (k1 << 4) | k2;
+            Instruction::SeByte(x, kk) => {
                 if self.data_registers[x as usize] == kk {
-                    self.program_counter += 2;
+                    self.skip();
                 }
             }
-            (4, x, k1, k2) => {
-                let kk = (k1 << 4) | k2;
+            Instruction::SneByte(x, kk) => {
                 if self.data_registers[x as usize] != kk {
-                    self.program_counter += 2;
+                    self.skip();
                 }
             }
-            (5, x, y, 0) => {
+            Instruction::Save(x, y) if xo_chip => {
+                let i = self.address_register as usize;
+                for offset in 0..=x.abs_diff(y) as usize {
+                    let register = register_in_range(x, y, offset);
+                    self.write_memory(i + offset, self.data_registers[register])?;
+                }
+            }
+            Instruction::Load(x, y) if xo_chip => {
+                let i = self.address_register as usize;
+                for offset in 0..=x.abs_diff(y) as usize {
+                    let register = register_in_range(x, y, offset);
+                    self.data_registers[register] = self.read_memory(i + offset)?;
+                }
+            }
+            Instruction::SeReg(x, y) => {
                 if self.data_registers[x as usize] == self.data_registers[y as usize] {
-                    self.program_counter += 2;
+                    self.skip();
                 }
             }
-            (6, x, k1, k2) => {
-                let kk = (k1 << 4) | k2;
+            Instruction::LdByte(x, kk) => {
                 self.data_registers[x as usize] = kk;
             }
-            (7, x, k1, k2) => {
-                let kk = (k1 << 4) | k2;
+            Instruction::AddByte(x, kk) => {
                 self.data_registers[x as usize] = self.data_registers[x as usize].wrapping_add(kk);
             }
-            (8, x, y, 0) => self.data_registers[x as usize] = self.data_registers[y as usize],
-            (8, x, y, 1) => self.data_registers[x as usize] |= self.data_registers[y as usize],
-            (8, x, y, 2) => self.data_registers[x as usize] &= self.data_registers[y as usize],
-            (8, x, y, 3) => self.data_registers[x as usize] ^= self.data_registers[y as usize],
-            (8, x, y, 4) => {
+            Instruction::LdReg(x, y) => {
+                self.data_registers[x as usize] = self.data_registers[y as usize]
+            }
+            Instruction::Or(x, y) => {
+                self.data_registers[x as usize] |= self.data_registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_registers[0xf] = 0;
+                }
+            }
+            Instruction::And(x, y) => {
+                self.data_registers[x as usize] &= self.data_registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_registers[0xf] = 0;
+                }
+            }
+            Instruction::Xor(x, y) => {
+                self.data_registers[x as usize] ^= self.data_registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.data_registers[0xf] = 0;
+                }
+            }
+            Instruction::AddReg(x, y) => {
                 let (sum, carry) = self.data_registers[x as usize]
                     .overflowing_add(self.data_registers[y as usize]);
                 self.data_registers[x as usize] = sum;
                 self.data_registers[0xf] = if carry { 1 } else { 0 };
             }
-            (8, x, y, 5) => {
+            Instruction::Sub(x, y) => {
                 let (diff, borrow) = self.data_registers[x as usize]
                     .overflowing_sub(self.data_registers[y as usize]);
                 self.data_registers[x as usize] = diff;
                 self.data_registers[0xf] = if !borrow { 1 } else { 0 };
             }
-            (8, x, _, 6) => {
+            Instruction::Shr(x, y) => {
                 let x = x as usize;
-                let lsb = self.data_registers[x] & 1;
-                self.data_registers[x] >>= 1;
+                let source = if self.quirks.shift { x } else { y as usize };
+                let lsb = self.data_registers[source] & 1;
+                self.data_registers[x] = self.data_registers[source] >> 1;
                 self.data_registers[0xf] = lsb;
             }
-            (8, x, y, 7) => {
+            Instruction::Subn(x, y) => {
                 let x = x as usize;
                 let y = y as usize;
                 let (diff, borrow) = self.data_registers[y].overflowing_sub(self.data_registers[x]);
-                self.data_registers[x as usize] = diff;
+                self.data_registers[x] = diff;
                 self.data_registers[0xf] = if !borrow { 1 } else { 0 };
             }
-            (8, x, _, 0xE) => {
+            Instruction::Shl(x, y) => {
                 let x = x as usize;
-                let msb = (self.data_registers[x] >> 7) & 1;
-                self.data_registers[x] <<= 1;
+                let source = if self.quirks.shift { x } else { y as usize };
+                let msb = (self.data_registers[source] >> 7) & 1;
+                self.data_registers[x] = self.data_registers[source] << 1;
                 self.data_registers[0xf] = msb;
             }
-            (9, x, y, 0) => {
+            Instruction::SneReg(x, y) => {
                 let x = x as usize;
                 let y = y as usize;
                 if self.data_registers[x] != self.data_registers[y] {
-                    self.program_counter += 2;
+                    self.skip();
                 }
             }
-            (0xA, _, _, _) => {
-                self.address_register = opcode & 0xFFF;
+            Instruction::LdI(nnn) => {
+                self.address_register = nnn;
             }
-            (0xB, _, _, _) => {
-                self.program_counter = self.data_registers[0] as u16 + (opcode & 0xFFF);
+            Instruction::JpV0(nnn) => {
+                let offset = if self.quirks.jump {
+                    (nnn >> 8) as usize
+                } else {
+                    0
+                };
+                self.program_counter = self.data_registers[offset] as u16 + nnn;
             }
-            (0xC, x, k1, k2) => {
+            Instruction::Rnd(x, kk) => {
                 let x = x as usize;
-                let kk = (k1 << 4) | k2;
-                let byte: u8 = rand::random();
-                self.data_registers[x] = kk & byte;
-            }
-            (0xD, x, y, n) => {
-                let x_cord = self.data_registers[x as usize] as u16;
-                let y_cord = self.data_registers[y as usize] as u16;
-                let mut flipped = false;
-                for y_line in 0..n {
-                    let addr = self.address_register + y_line as u16;
-                    let pixels = self.memory[addr as usize];
-                    for x_line in 0..8 {
-                        if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                            let x = (x_cord + x_line) as usize % SCREEN_WIDTH as usize;
-                            let y = (y_cord + y_line as u16) as usize % (SCREEN_HEIGHT as usize);
-
-                            let idx = x + (SCREEN_WIDTH as usize) * y;
-                            flipped |= self.frame_buffer[idx];
-                            self.frame_buffer[idx] ^= true;
-                        }
+                self.data_registers[x] = kk & self.rng.next_byte();
+            }
+            Instruction::Drw(x, y, n) => {
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.program_counter = self.program_counter.wrapping_sub(2);
+                        return Ok(());
                     }
+                    self.vblank = false;
                 }
-                self.data_registers[0xf] = if flipped { 1 } else { 0 };
+                let x_cord = self.data_registers[x as usize] as usize;
+                let y_cord = self.data_registers[y as usize] as usize;
+                let (columns, rows) = if n == 0 && super_chip {
+                    (16, 16)
+                } else {
+                    (8, n as usize)
+                };
+                let (collided_rows, clipped_rows) =
+                    self.draw_sprite(x_cord, y_cord, columns, rows)?;
+                self.data_registers[0xf] = if super_chip && !xo_chip && self.hires {
+                    collided_rows + clipped_rows
+                } else {
+                    collided_rows.min(1)
+                };
             }
-            (0xE, x, 9, 0xE) => {
+            Instruction::Skp(x) => {
                 let x = x as usize;
                 let vx = self.data_registers[x];
-                if self.inputs[vx as usize] {
-                    self.program_counter += 2;
+                if self.input(vx)? {
+                    self.skip();
                 }
             }
-            (0xE, x, 0xA, 1) => {
+            Instruction::Sknp(x) => {
                 let x = x as usize;
                 let vx = self.data_registers[x];
-                if !self.inputs[vx as usize] {
-                    self.program_counter += 2;
+                if !self.input(vx)? {
+                    self.skip();
                 }
             }
-            (0xF, x, 0, 7) => {
+            Instruction::LdVxDt(x) => {
                 let x = x as usize;
                 self.data_registers[x] = self.delay_timer;
             }
-            (0xF, x, 0, 0xA) => {
+            Instruction::LdVxK(x) => {
                 let x = x as usize;
-                let mut pressed = false;
-                for i in 0..NUM_KEYS {
-                    if self.inputs[i] {
-                        self.data_registers[x] = i as u8;
-                        pressed = true;
-                        break;
+                // A key that completed the previous `FX0A` on press must be let go first.
+                let blocked = self.key_wait.filter(|key| self.inputs[*key as usize]);
+                let pressed = (0..NUM_KEYS as u8)
+                    .find(|key| self.inputs[*key as usize] && Some(*key) != blocked);
+                let key = if self.quirks.key_release {
+                    match self.key_wait {
+                        Some(key) if !self.inputs[key as usize] => Some(key),
+                        Some(_) => None,
+                        None => {
+                            self.key_wait = pressed;
+                            None
+                        }
+                    }
+                } else {
+                    self.key_wait = blocked;
+                    pressed
+                };
+                match key {
+                    Some(key) => {
+                        self.data_registers[x] = key;
+                        self.key_wait = (!self.quirks.key_release).then_some(key);
+                        self.waiting_for_key = false;
+                    }
+                    None => {
+                        self.waiting_for_key = true;
+                        self.program_counter = self.program_counter.wrapping_sub(2);
                     }
-                }
-                if !pressed {
-                    self.program_counter -= 2;
                 }
             }
-            (0xF, x, 1, 5) => {
+            Instruction::LdDtVx(x) => {
                 let x = x as usize;
                 self.delay_timer = self.data_registers[x];
             }
-            (0xF, x, 1, 8) => {
+            Instruction::LdStVx(x) => {
                 let x = x as usize;
                 self.sound_timer = self.data_registers[x];
             }
-            (0xF, x, 1, 0xE) => {
+            Instruction::AddIVx(x) => {
                 let x = x as usize;
                 self.address_register = self
                     .address_register
                     .wrapping_add(self.data_registers[x].into());
             }
-            (0xF, x, 2, 9) => {
+            Instruction::LdFVx(x) => {
                 let x = x as usize;
                 let c = self.data_registers[x] as u16;
                 self.address_register = c * 5;
             }
-            (0xF, x, 3, 3) => {
+            Instruction::LdHfVx(x) if super_chip => {
+                let x = x as usize;
+                let c = (self.data_registers[x] & 0xF) as u16;
+                self.address_register = BIG_FONT_ADDR as u16 + c * 10;
+            }
+            Instruction::LdBVx(x) => {
                 let x = x as usize;
-                let vx = self.data_registers[x] as f32;
+                let vx = self.data_registers[x];
 
-                let hundreds = (vx / 100.0).floor() as u8;
-                let tens = ((vx - (hundreds as f32 * 100.0)) / 10.0).floor() as u8;
-                let ones = (vx % 10.0) as u8;
+                let hundreds = vx / 100;
+                let tens = vx / 10 % 10;
+                let ones = vx % 10;
 
                 let i = self.address_register as usize;
-                self.memory[i] = hundreds;
-                self.memory[i + 1] = tens;
-                self.memory[i + 2] = ones;
+                self.write_memory(i, hundreds)?;
+                self.write_memory(i + 1, tens)?;
+                self.write_memory(i + 2, ones)?;
             }
-            (0xF, x, 5, 5) => {
+            Instruction::LdMemVx(x) => {
                 let x = x as usize;
                 let i = self.address_register as usize;
                 for idx in 0..=x {
-                    self.memory[i + idx] = self.data_registers[idx];
+                    self.write_memory(i + idx, self.data_registers[idx])?;
+                }
+                if self.quirks.memory_increment {
+                    self.address_register = self.address_register.wrapping_add(x as u16 + 1);
                 }
             }
-            (0xF, x, 6, 5) => {
+            Instruction::LdVxMem(x) => {
                 let x = x as usize;
                 let i = self.address_register as usize;
                 for idx in 0..=x {
-                    self.data_registers[idx] = self.memory[i + idx];
+                    self.data_registers[idx] = self.read_memory(i + idx)?;
+                }
+                if self.quirks.memory_increment {
+                    self.address_register = self.address_register.wrapping_add(x as u16 + 1);
                 }
             }
-            (_, _, _, _) => unimplemented!("opcode {:04x}", opcode),
-        }
-    }
-    pub fn tick_timers(&mut self, sound_timer_done: &mut bool) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                *sound_timer_done = true;
+            Instruction::LdILong(nnnn) if xo_chip => {
+                self.address_register = nnnn;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
-            self.sound_timer -= 1;
+            Instruction::Plane(n) if xo_chip => self.plane_mask = n & 0b11,
+            Instruction::Audio if xo_chip => {
+                let i = self.address_register as usize;
+                for idx in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[idx] = self.read_memory(i + idx)?;
+                }
+            }
+            Instruction::Pitch(x) if xo_chip => self.pitch = self.data_registers[x as usize],
+            Instruction::LdRVx(x) if super_chip => {
+                let x = (x as usize).min(self.rpl_flag_count() - 1);
+                self.rpl_flags[..=x].copy_from_slice(&self.data_registers[..=x]);
+            }
+            Instruction::LdVxR(x) if super_chip => {
+                let x = (x as usize).min(self.rpl_flag_count() - 1);
+                self.data_registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+            }
+            _ => return Err(self.unknown_opcode()),
         }
+        Ok(())
+    }
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+    /// Whether the buzzer should be sounding, which is for as long as the sound timer is non-zero.
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+}
+
+/// The register `offset` steps from `Vx` towards `Vy`, as transferred by `5XY2`/`5XY3`.
+fn register_in_range(x: u8, y: u8, offset: usize) -> usize {
+    if x <= y {
+        x as usize + offset
+    } else {
+        x as usize - offset
     }
 }
@@ -0,0 +1,427 @@
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use crate::disasm::Instruction;
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+    /// Parses the names used by the disassembler: `V0`-`VF`, `I`, `DT` and `ST`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "DT" => Ok(Register::DelayTimer),
+            "ST" => Ok(Register::SoundTimer),
+            upper => upper
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register {name}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+}
+
+/// Why the debugger paused execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint {
+        pc: u16,
+    },
+    /// The instruction at `pc` accessed the watched address `addr`.
+    Watchpoint {
+        pc: u16,
+        addr: u16,
+        access: Access,
+    },
+    RegisterChanged {
+        pc: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    Step,
+    StepOver,
+    StepOut,
+    Frame,
+    Halted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at {:#05X}", pc),
+            StopReason::Watchpoint { pc, addr, access } => {
+                let access = match access {
+                    Access::Read => "read",
+                    _ => "write",
+                };
+                write!(f, "{} of {:#05X} by {:#05X}", access, addr, pc)
+            }
+            StopReason::RegisterChanged {
+                pc,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} changed from {:#X} to {:#X} at {:#05X}",
+                register, old, new, pc
+            ),
+            StopReason::Step => write!(f, "step"),
+            StopReason::StepOver => write!(f, "step over"),
+            StopReason::StepOut => write!(f, "step out"),
+            StopReason::Frame => write!(f, "end of frame"),
+            StopReason::Halted => write!(f, "program halted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    Running,
+    /// Runs until the subroutine called at the current depth returns to `pc`.
+    StepOver {
+        pc: u16,
+        stack_pointer: u8,
+    },
+    /// Runs until a `00EE` drops the stack below `stack_pointer`.
+    StepOut {
+        stack_pointer: u8,
+    },
+    Frame,
+}
+
+/// Drives a `CPU` under breakpoint, watchpoint and stepping control.
+///
+/// Frontends call `run_frame` once per frame in place of ticking the CPU themselves; it does
+/// nothing while paused, so stepping commands can be issued between frames. Step over and step
+/// out resume execution and complete on a later `run_frame`, keeping the frontend responsive while
+/// the program runs.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    watched_registers: BTreeSet<Register>,
+    mode: Mode,
+    /// Whether `reset` leaves the debugger paused rather than running.
+    start_paused: bool,
+    /// Set when resuming so that the breakpoint at the current PC does not fire straight away.
+    resuming: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watched_registers: BTreeSet::new(),
+            mode: Mode::Running,
+            start_paused: false,
+            resuming: false,
+        }
+    }
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access) {
+        self.watchpoints.push(Watchpoint { range, access });
+    }
+    pub fn remove_watchpoint(&mut self, range: &RangeInclusive<u16>) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| &watchpoint.range != range);
+        self.watchpoints.len() != len
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+    pub fn watch_register(&mut self, register: Register) {
+        self.watched_registers.insert(register);
+    }
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        self.watched_registers.remove(&register)
+    }
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.watched_registers.clear();
+    }
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+    pub fn resume(&mut self) {
+        self.start(Mode::Running);
+    }
+    /// Chooses whether the program starts paused, now and whenever the machine is reset.
+    pub fn set_start_paused(&mut self, paused: bool) {
+        self.start_paused = paused;
+        self.reset();
+    }
+    /// Returns to the start mode, e.g. out of the pause a halted program leaves behind.
+    /// Breakpoints and watchpoints are kept.
+    pub fn reset(&mut self) {
+        self.mode = if self.start_paused {
+            Mode::Paused
+        } else {
+            Mode::Running
+        };
+        self.resuming = false;
+    }
+    /// The instruction the CPU executes next.
+    pub fn current_instruction(&self, cpu: &CPU) -> Option<Instruction> {
+        let memory = &cpu.memory[..cpu.variant.memory_size()];
+        Instruction::decode_at_for(memory, cpu.program_counter as usize, cpu.variant)
+    }
+    /// Executes a single instruction and stays paused.
+    pub fn step_into(&mut self, cpu: &mut CPU) -> Result<StopReason, CpuError> {
+        self.mode = Mode::Paused;
+        Ok(self.execute(cpu)?.unwrap_or(StopReason::Step))
+    }
+    /// Executes the next instruction, running a `2NNN` call through to its return as one step.
+    /// Calls complete on the following `run_frame`s, which return `StopReason::StepOver`.
+    pub fn step_over(&mut self, cpu: &mut CPU) -> Result<Option<StopReason>, CpuError> {
+        match self.current_instruction(cpu) {
+            Some(instruction @ Instruction::Call(_)) => {
+                self.start(Mode::StepOver {
+                    pc: cpu.program_counter.wrapping_add(instruction.size() as u16),
+                    stack_pointer: cpu.stack_pointer,
+                });
+                Ok(None)
+            }
+            _ => self.step_into(cpu).map(Some),
+        }
+    }
+    /// Resumes until the current subroutine returns, reported by a later `run_frame` as
+    /// `StopReason::StepOut`. Outside of any subroutine this is the same as `resume`.
+    pub fn step_out(&mut self, cpu: &CPU) {
+        if cpu.stack_pointer == 0 {
+            self.resume();
+        } else {
+            self.start(Mode::StepOut {
+                stack_pointer: cpu.stack_pointer,
+            });
+        }
+    }
+    /// Resumes for exactly one frame, pausing again when the next `run_frame` finishes.
+    pub fn step_frame(&mut self) {
+        self.start(Mode::Frame);
+    }
+    /// Executes up to `cycles` instructions unless paused, and reports why execution stopped if a
+    /// breakpoint, watchpoint or stepping command ended it.
+    pub fn run_frame(
+        &mut self,
+        cpu: &mut CPU,
+        cycles: usize,
+    ) -> Result<Option<StopReason>, CpuError> {
+        let (_, reason) = self.run(cpu, cycles)?;
+        Ok(reason.or_else(|| self.end_frame()))
+    }
+    /// Executes up to `cycles` instructions unless paused, returning how many ran and why
+    /// execution stopped early, if it did.
+    pub(crate) fn run(
+        &mut self,
+        cpu: &mut CPU,
+        cycles: usize,
+    ) -> Result<(usize, Option<StopReason>), CpuError> {
+        if self.mode == Mode::Paused {
+            return Ok((0, None));
+        }
+        if self.mode == Mode::Running && self.is_unused() {
+            self.resuming = false;
+            for executed in 0..cycles {
+                cpu.tick()?;
+                if cpu.is_halted() {
+                    self.mode = Mode::Paused;
+                    return Ok((executed + 1, Some(StopReason::Halted)));
+                }
+            }
+            return Ok((cycles, None));
+        }
+        for executed in 0..cycles {
+            let pc = cpu.program_counter;
+            if !self.resuming && self.breakpoints.contains(&pc) {
+                self.mode = Mode::Paused;
+                return Ok((executed, Some(StopReason::Breakpoint { pc })));
+            }
+            self.resuming = false;
+            let mode = self.mode;
+            if let Some(reason) = self.execute(cpu)? {
+                self.mode = Mode::Paused;
+                return Ok((executed + 1, Some(reason)));
+            }
+            let reason = match mode {
+                Mode::StepOver { pc, stack_pointer }
+                    if cpu.program_counter == pc && cpu.stack_pointer == stack_pointer =>
+                {
+                    StopReason::StepOver
+                }
+                Mode::StepOut { stack_pointer } if cpu.stack_pointer < stack_pointer => {
+                    StopReason::StepOut
+                }
+                _ => continue,
+            };
+            self.mode = Mode::Paused;
+            return Ok((executed + 1, Some(reason)));
+        }
+        Ok((cycles, None))
+    }
+    /// Pauses at the end of the frame requested by `step_frame`.
+    pub(crate) fn end_frame(&mut self) -> Option<StopReason> {
+        if self.mode == Mode::Frame {
+            self.mode = Mode::Paused;
+            return Some(StopReason::Frame);
+        }
+        None
+    }
+    fn is_unused(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.watched_registers.is_empty()
+    }
+    fn start(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.resuming = true;
+    }
+    /// Ticks the CPU once and checks the watchpoints against what the instruction touched.
+    fn execute(&mut self, cpu: &mut CPU) -> Result<Option<StopReason>, CpuError> {
+        let pc = cpu.program_counter;
+        let accesses = if self.watchpoints.is_empty() {
+            None
+        } else {
+            self.current_instruction(cpu)
+                .and_then(|instruction| memory_access(cpu, instruction))
+        };
+        let registers: Vec<(Register, u16)> = self
+            .watched_registers
+            .iter()
+            .map(|register| (*register, register_value(cpu, *register)))
+            .collect();
+        cpu.tick()?;
+
+        if let Some((range, access)) = accesses {
+            for watchpoint in &self.watchpoints {
+                let start = *range.start().max(watchpoint.range.start());
+                let end = *range.end().min(watchpoint.range.end());
+                if start <= end && watchpoint.access.covers(access) {
+                    return Ok(Some(StopReason::Watchpoint {
+                        pc,
+                        addr: start,
+                        access,
+                    }));
+                }
+            }
+        }
+        for (register, old) in registers {
+            let new = register_value(cpu, register);
+            if new != old {
+                return Ok(Some(StopReason::RegisterChanged {
+                    pc,
+                    register,
+                    old,
+                    new,
+                }));
+            }
+        }
+        if cpu.is_halted() {
+            return Ok(Some(StopReason::Halted));
+        }
+        Ok(None)
+    }
+}
+
+fn register_value(cpu: &CPU, register: Register) -> u16 {
+    match register {
+        Register::V(x) => cpu.data_registers[x as usize & 0xF] as u16,
+        Register::I => cpu.address_register,
+        Register::DelayTimer => cpu.delay_timer as u16,
+        Register::SoundTimer => cpu.sound_timer as u16,
+    }
+}
+
+/// The memory `instruction` is about to read or write when executed on `cpu`, if any.
+fn memory_access(cpu: &CPU, instruction: Instruction) -> Option<(RangeInclusive<u16>, Access)> {
+    let i = cpu.address_register;
+    let (len, access) = match instruction {
+        Instruction::Drw(_, _, n) => {
+            if cpu.quirks.display_wait && !cpu.vblank {
+                return None;
+            }
+            let bytes = if n == 0 && cpu.variant != Variant::Chip8 {
+                32
+            } else {
+                n as u16
+            };
+            (bytes * cpu.plane_mask.count_ones() as u16, Access::Read)
+        }
+        Instruction::LdBVx(_) => (3, Access::Write),
+        Instruction::LdMemVx(x) => (x as u16 + 1, Access::Write),
+        Instruction::LdVxMem(x) => (x as u16 + 1, Access::Read),
+        Instruction::Save(x, y) => (x.abs_diff(y) as u16 + 1, Access::Write),
+        Instruction::Load(x, y) => (x.abs_diff(y) as u16 + 1, Access::Read),
+        Instruction::Audio => (AUDIO_PATTERN_SIZE as u16, Access::Read),
+        _ => return None,
+    };
+    if len == 0 {
+        return None;
+    }
+    Some((i..=i.saturating_add(len - 1), access))
+}
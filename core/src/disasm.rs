@@ -0,0 +1,404 @@
+#[cfg(feature = "alloc")]
+use alloc::collections::BTreeSet;
+#[cfg(feature = "alloc")]
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+use core::fmt;
+
+use crate::Variant;
+#[cfg(feature = "alloc")]
+use crate::START_ADDR;
+
+#[cfg(feature = "alloc")]
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),
+    Cls,
+    Ret,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeByte(u8, u8),
+    SneByte(u8, u8),
+    SeReg(u8, u8),
+    Save(u8, u8),
+    Load(u8, u8),
+    LdByte(u8, u8),
+    AddByte(u8, u8),
+    LdReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    Shr(u8, u8),
+    Subn(u8, u8),
+    Shl(u8, u8),
+    SneReg(u8, u8),
+    LdI(u16),
+    JpV0(u16),
+    Rnd(u8, u8),
+    Drw(u8, u8, u8),
+    Skp(u8),
+    Sknp(u8),
+    LdILong(u16),
+    Plane(u8),
+    Audio,
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdHfVx(u8),
+    LdBVx(u8),
+    Pitch(u8),
+    LdMemVx(u8),
+    LdVxMem(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decodes `opcode`. `next` is the word that follows it and is only used by the four byte
+    /// XO-CHIP `F000 NNNN`.
+    pub fn decode(opcode: u16, next: u16) -> Instruction {
+        let d1 = ((opcode & 0xF000) >> 12) as u8;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nnn = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+        match (d1, x, y, n) {
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xC, n) => Instruction::ScrollDown(n),
+            (0, 0, 0xD, n) => Instruction::ScrollUp(n),
+            (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Low,
+            (0, 0, 0xF, 0xF) => Instruction::High,
+            (0, _, _, _) => Instruction::Sys(nnn),
+            (1, _, _, _) => Instruction::Jp(nnn),
+            (2, _, _, _) => Instruction::Call(nnn),
+            (3, x, _, _) => Instruction::SeByte(x, kk),
+            (4, x, _, _) => Instruction::SneByte(x, kk),
+            (5, x, y, 0) => Instruction::SeReg(x, y),
+            (5, x, y, 2) => Instruction::Save(x, y),
+            (5, x, y, 3) => Instruction::Load(x, y),
+            (6, x, _, _) => Instruction::LdByte(x, kk),
+            (7, x, _, _) => Instruction::AddByte(x, kk),
+            (8, x, y, 0) => Instruction::LdReg(x, y),
+            (8, x, y, 1) => Instruction::Or(x, y),
+            (8, x, y, 2) => Instruction::And(x, y),
+            (8, x, y, 3) => Instruction::Xor(x, y),
+            (8, x, y, 4) => Instruction::AddReg(x, y),
+            (8, x, y, 5) => Instruction::Sub(x, y),
+            (8, x, y, 6) => Instruction::Shr(x, y),
+            (8, x, y, 7) => Instruction::Subn(x, y),
+            (8, x, y, 0xE) => Instruction::Shl(x, y),
+            (9, x, y, 0) => Instruction::SneReg(x, y),
+            (0xA, _, _, _) => Instruction::LdI(nnn),
+            (0xB, _, _, _) => Instruction::JpV0(nnn),
+            (0xC, x, _, _) => Instruction::Rnd(x, kk),
+            (0xD, x, y, n) => Instruction::Drw(x, y, n),
+            (0xE, x, 9, 0xE) => Instruction::Skp(x),
+            (0xE, x, 0xA, 1) => Instruction::Sknp(x),
+            (0xF, 0, 0, 0) => Instruction::LdILong(next),
+            (0xF, n, 0, 1) => Instruction::Plane(n),
+            (0xF, 0, 0, 2) => Instruction::Audio,
+            (0xF, x, 0, 7) => Instruction::LdVxDt(x),
+            (0xF, x, 0, 0xA) => Instruction::LdVxK(x),
+            (0xF, x, 1, 5) => Instruction::LdDtVx(x),
+            (0xF, x, 1, 8) => Instruction::LdStVx(x),
+            (0xF, x, 1, 0xE) => Instruction::AddIVx(x),
+            (0xF, x, 2, 9) => Instruction::LdFVx(x),
+            (0xF, x, 3, 0) => Instruction::LdHfVx(x),
+            (0xF, x, 3, 3) => Instruction::LdBVx(x),
+            (0xF, x, 3, 0xA) => Instruction::Pitch(x),
+            (0xF, x, 5, 5) => Instruction::LdMemVx(x),
+            (0xF, x, 6, 5) => Instruction::LdVxMem(x),
+            (0xF, x, 7, 5) => Instruction::LdRVx(x),
+            (0xF, x, 8, 5) => Instruction::LdVxR(x),
+            (_, _, _, _) => Instruction::Unknown(opcode),
+        }
+    }
+    /// Decodes `opcode` as `variant` executes it: `F000` is only the four byte `LD I, LONG` on
+    /// XO-CHIP and an unknown two byte instruction elsewhere.
+    pub fn decode_for(opcode: u16, next: u16, variant: Variant) -> Instruction {
+        if opcode == 0xF000 && !variant.is_xo_chip() {
+            Instruction::Unknown(opcode)
+        } else {
+            Instruction::decode(opcode, next)
+        }
+    }
+    /// Like `decode_at`, decoding as `variant` executes the instruction.
+    pub fn decode_at_for(memory: &[u8], addr: usize, variant: Variant) -> Option<Instruction> {
+        match Instruction::decode_at(memory, addr) {
+            Some(Instruction::LdILong(_)) if !variant.is_xo_chip() => {
+                Some(Instruction::Unknown(0xF000))
+            }
+            instruction => instruction,
+        }
+    }
+    /// Decodes the instruction stored at `addr`, or `None` if it runs past the end of `memory`.
+    pub fn decode_at(memory: &[u8], addr: usize) -> Option<Instruction> {
+        let word = |addr: usize| -> Option<u16> {
+            Some(u16::from_be_bytes([
+                *memory.get(addr)?,
+                *memory.get(addr + 1)?,
+            ]))
+        };
+        let opcode = word(addr)?;
+        if opcode == 0xF000 {
+            Some(Instruction::LdILong(word(addr + 2)?))
+        } else {
+            Some(Instruction::decode(opcode, 0))
+        }
+    }
+    /// Size of the encoded instruction in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(_)
+                | Instruction::Sknp(_)
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Save(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::Load(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong(nnnn) => write!(f, "LD I, LONG 0x{:04X}", nnnn),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdMemVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxMem(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code {
+        addr: u16,
+        instruction: Instruction,
+    },
+    /// Bytes never reached by the control flow analysis. `referenced` is set for runs that follow
+    /// an address loaded into `I`, which is how sprites and other tables are found.
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+        referenced: bool,
+    },
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+}
+
+#[cfg(feature = "alloc")]
+impl Disassembly {
+    /// Renders the disassembly as source for the `asm` module, with addresses as comments.
+    /// Assembling it reproduces the original ROM byte for byte.
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for line in &self.lines {
+            let (addr, text) = match line {
+                Line::Code { addr, instruction } => (addr, instruction.to_string()),
+                Line::Data { addr, bytes, .. } => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    (addr, format!("DB {}", bytes.join(", ")))
+                }
+            };
+            source.push_str(&format!("    {:<40}; 0x{:03X}\n", text, addr));
+        }
+        source
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Code { addr, instruction } => {
+                    writeln!(f, "0x{:03X}  {}", addr, instruction)?;
+                }
+                Line::Data {
+                    addr,
+                    bytes,
+                    referenced,
+                } => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    let note = if *referenced {
+                        "  ; referenced by I"
+                    } else {
+                        ""
+                    };
+                    writeln!(f, "0x{:03X}  DB {}{}", addr, bytes.join(", "), note)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles `rom` as loaded at the program start address, following jumps, calls and skips
+/// from the entry point to separate code from data.
+#[cfg(feature = "alloc")]
+pub fn disassemble(rom: &[u8]) -> Disassembly {
+    let origin = START_ADDR as usize;
+    let mut memory = vec![0; origin];
+    memory.extend_from_slice(rom);
+    let end = memory.len();
+
+    let mut code_starts = BTreeSet::new();
+    let mut covered = vec![false; end];
+    let mut references = BTreeSet::new();
+    let mut pending = vec![origin];
+    while let Some(addr) = pending.pop() {
+        if addr < origin || code_starts.contains(&addr) {
+            continue;
+        }
+        let instruction = match Instruction::decode_at(&memory, addr) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let span = addr..addr + instruction.size();
+        if covered[span.clone()].iter().any(|covered| *covered) {
+            continue;
+        }
+        covered[span].iter_mut().for_each(|covered| *covered = true);
+        code_starts.insert(addr);
+
+        let next = addr + instruction.size();
+        match instruction {
+            Instruction::Jp(nnn) => pending.push(nnn as usize),
+            Instruction::Call(nnn) => {
+                pending.push(nnn as usize);
+                pending.push(next);
+            }
+            Instruction::Ret | Instruction::Exit | Instruction::JpV0(_) => {}
+            Instruction::Unknown(_) => {}
+            Instruction::LdI(nnn) => {
+                references.insert(nnn as usize);
+                pending.push(next);
+            }
+            Instruction::LdILong(nnnn) => {
+                references.insert(nnnn as usize);
+                pending.push(next);
+            }
+            instruction if instruction.is_skip() => {
+                pending.push(next);
+                let skipped = Instruction::decode_at(&memory, next).map_or(2, |i| i.size());
+                pending.push(next + skipped);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut addr = origin;
+    let mut referenced = false;
+    while addr < end {
+        if code_starts.contains(&addr) {
+            if let Some(instruction) = Instruction::decode_at(&memory, addr) {
+                lines.push(Line::Code {
+                    addr: addr as u16,
+                    instruction,
+                });
+                addr += instruction.size();
+                referenced = false;
+                continue;
+            }
+        }
+        let start = addr;
+        referenced |= references.contains(&start);
+        let mut bytes = vec![memory[addr]];
+        addr += 1;
+        while addr < end
+            && !covered[addr]
+            && !references.contains(&addr)
+            && bytes.len() < DATA_BYTES_PER_LINE
+        {
+            bytes.push(memory[addr]);
+            addr += 1;
+        }
+        lines.push(Line::Data {
+            addr: start as u16,
+            bytes,
+            referenced,
+        });
+    }
+    Disassembly { lines }
+}
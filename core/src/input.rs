@@ -0,0 +1,23 @@
+use core::time::Duration;
+
+/// A key of the hex keypad going down or up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyEvent {
+    /// Keypad key, `0x0` to `0xF`.
+    pub key: u8,
+    pub pressed: bool,
+    /// When the event happened, measured from any fixed point the frontend chooses. Only used to
+    /// keep events in order.
+    pub timestamp: Duration,
+}
+
+impl KeyEvent {
+    pub fn new(key: u8, pressed: bool, timestamp: Duration) -> Self {
+        Self {
+            key,
+            pressed,
+            timestamp,
+        }
+    }
+}
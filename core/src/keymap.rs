@@ -0,0 +1,298 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::*;
+
+/// A key of the host keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    /// A key by its position, whatever the layout prints on it, as a USB HID keyboard usage ID.
+    /// These are the values of SDL scancodes and `HostKey::from_code` converts the names of
+    /// `KeyboardEvent::code`.
+    Scancode(u16),
+    /// A key by the character it types, lowercase, as in SDL keycodes and `KeyboardEvent::key`.
+    Character(char),
+}
+
+/// `KeyboardEvent::code` names of the keys of a US keyboard and their HID usage IDs.
+const CODES: &[(&str, u16)] = &[
+    ("KeyA", 4),
+    ("KeyB", 5),
+    ("KeyC", 6),
+    ("KeyD", 7),
+    ("KeyE", 8),
+    ("KeyF", 9),
+    ("KeyG", 10),
+    ("KeyH", 11),
+    ("KeyI", 12),
+    ("KeyJ", 13),
+    ("KeyK", 14),
+    ("KeyL", 15),
+    ("KeyM", 16),
+    ("KeyN", 17),
+    ("KeyO", 18),
+    ("KeyP", 19),
+    ("KeyQ", 20),
+    ("KeyR", 21),
+    ("KeyS", 22),
+    ("KeyT", 23),
+    ("KeyU", 24),
+    ("KeyV", 25),
+    ("KeyW", 26),
+    ("KeyX", 27),
+    ("KeyY", 28),
+    ("KeyZ", 29),
+    ("Digit1", 30),
+    ("Digit2", 31),
+    ("Digit3", 32),
+    ("Digit4", 33),
+    ("Digit5", 34),
+    ("Digit6", 35),
+    ("Digit7", 36),
+    ("Digit8", 37),
+    ("Digit9", 38),
+    ("Digit0", 39),
+    ("Enter", 40),
+    ("Escape", 41),
+    ("Backspace", 42),
+    ("Tab", 43),
+    ("Space", 44),
+    ("Minus", 45),
+    ("Equal", 46),
+    ("BracketLeft", 47),
+    ("BracketRight", 48),
+    ("Backslash", 49),
+    ("IntlHash", 50),
+    ("Semicolon", 51),
+    ("Quote", 52),
+    ("Backquote", 53),
+    ("Comma", 54),
+    ("Period", 55),
+    ("Slash", 56),
+    ("CapsLock", 57),
+    ("F1", 58),
+    ("F2", 59),
+    ("F3", 60),
+    ("F4", 61),
+    ("F5", 62),
+    ("F6", 63),
+    ("F7", 64),
+    ("F8", 65),
+    ("F9", 66),
+    ("F10", 67),
+    ("F11", 68),
+    ("F12", 69),
+    ("PrintScreen", 70),
+    ("ScrollLock", 71),
+    ("Pause", 72),
+    ("Insert", 73),
+    ("Home", 74),
+    ("PageUp", 75),
+    ("Delete", 76),
+    ("End", 77),
+    ("PageDown", 78),
+    ("ArrowRight", 79),
+    ("ArrowLeft", 80),
+    ("ArrowDown", 81),
+    ("ArrowUp", 82),
+    ("NumLock", 83),
+    ("NumpadDivide", 84),
+    ("NumpadMultiply", 85),
+    ("NumpadSubtract", 86),
+    ("NumpadAdd", 87),
+    ("NumpadEnter", 88),
+    ("Numpad1", 89),
+    ("Numpad2", 90),
+    ("Numpad3", 91),
+    ("Numpad4", 92),
+    ("Numpad5", 93),
+    ("Numpad6", 94),
+    ("Numpad7", 95),
+    ("Numpad8", 96),
+    ("Numpad9", 97),
+    ("Numpad0", 98),
+    ("NumpadDecimal", 99),
+    ("IntlBackslash", 100),
+    ("ContextMenu", 101),
+    ("NumpadEqual", 103),
+    ("ControlLeft", 224),
+    ("ShiftLeft", 225),
+    ("AltLeft", 226),
+    ("MetaLeft", 227),
+    ("ControlRight", 228),
+    ("ShiftRight", 229),
+    ("AltRight", 230),
+    ("MetaRight", 231),
+];
+
+impl HostKey {
+    /// The key at the position `KeyboardEvent::code` names, e.g. `"KeyQ"` or `"Numpad5"`.
+    pub fn from_code(code: &str) -> Option<HostKey> {
+        CODES
+            .iter()
+            .find(|(name, _)| *name == code)
+            .map(|(_, scancode)| HostKey::Scancode(*scancode))
+    }
+    /// The key typing `character`, in either case.
+    pub fn from_char(character: char) -> HostKey {
+        HostKey::Character(character.to_lowercase().next().unwrap_or(character))
+    }
+    /// The `KeyboardEvent::code` name of a key given by position.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            HostKey::Scancode(scancode) => CODES
+                .iter()
+                .find(|(_, code)| code == scancode)
+                .map(|(name, _)| *name),
+            HostKey::Character(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.code()) {
+            (_, Some(code)) => write!(f, "{code}"),
+            (HostKey::Scancode(scancode), None) => write!(f, "scancode {scancode}"),
+            (HostKey::Character(character), None) => write!(f, "{character}"),
+        }
+    }
+}
+
+impl FromStr for HostKey {
+    type Err = String;
+    /// Parses a `KeyboardEvent::code` name such as `KeyQ` as a key by position and a single
+    /// character such as `q` as the key typing it.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(character), None) => Ok(HostKey::from_char(character)),
+            _ => HostKey::from_code(name).ok_or_else(|| format!("unknown key {name}")),
+        }
+    }
+}
+
+/// Keypad keys in the order they sit on the COSMAC VIP's 4x4 keypad, row by row.
+const KEYPAD: [u8; NUM_KEYS] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Which host keys press which keypad keys.
+///
+/// A host key presses at most one keypad key, while a keypad key may be bound to any number of
+/// host keys. Frontends look up keys by position first and by character second, so a map can mix
+/// both kinds of binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: Vec<(HostKey, u8)>,
+}
+
+impl KeyMap {
+    /// Names accepted by `preset`.
+    pub const PRESET_NAMES: [&'static str; 5] = ["default", "qwerty", "azerty", "dvorak", "numpad"];
+
+    /// A map with no bindings.
+    pub fn empty() -> Self {
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+    /// Looks up a preset by name.
+    pub fn preset(name: &str) -> Option<KeyMap> {
+        match name {
+            "default" => Some(KeyMap::default()),
+            "qwerty" => Some(KeyMap::qwerty()),
+            "azerty" => Some(KeyMap::azerty()),
+            "dvorak" => Some(KeyMap::dvorak()),
+            "numpad" => Some(KeyMap::numpad()),
+            _ => None,
+        }
+    }
+    /// The characters of the `1234`/`QWER`/`ASDF`/`ZXCV` block of a QWERTY keyboard.
+    pub fn qwerty() -> Self {
+        Self::from_characters("1234qwerasdfzxcv")
+    }
+    /// The same block on an AZERTY keyboard, whose top row types `&é"'` without shift.
+    pub fn azerty() -> Self {
+        Self::from_characters("&é\"'azerqsdfwxcv")
+    }
+    /// The same block on a Dvorak keyboard.
+    pub fn dvorak() -> Self {
+        Self::from_characters("1234',.paoeu;qjk")
+    }
+    /// The numeric keypad: its digits press the same digits and `.`, `Enter`, `+`, `-`, `*`
+    /// and `/` press `A` to `F`.
+    pub fn numpad() -> Self {
+        let mut map = Self::empty();
+        for key in 0..=9 {
+            let code = if key == 0 { 98 } else { 88 + key as u16 };
+            map.bind(HostKey::Scancode(code), key);
+        }
+        for (key, code) in (0xA..=0xF).zip([99, 88, 87, 86, 85, 84]) {
+            map.bind(HostKey::Scancode(code), key);
+        }
+        map
+    }
+    fn from_characters(characters: &str) -> Self {
+        let mut map = Self::empty();
+        for (character, key) in characters.chars().zip(KEYPAD) {
+            map.bind(HostKey::Character(character), key);
+        }
+        map
+    }
+    /// Makes `host` press keypad `key`, replacing what `host` was bound to.
+    pub fn bind(&mut self, host: HostKey, key: u8) {
+        let key = key & 0xF;
+        match self.bindings.iter_mut().find(|(bound, _)| *bound == host) {
+            Some(binding) => binding.1 = key,
+            None => self.bindings.push((host, key)),
+        }
+    }
+    /// Removes the binding of `host`, returning the keypad key it pressed.
+    pub fn unbind(&mut self, host: HostKey) -> Option<u8> {
+        let idx = self.bindings.iter().position(|(bound, _)| *bound == host)?;
+        Some(self.bindings.remove(idx).1)
+    }
+    /// Removes every binding of keypad `key`.
+    pub fn unbind_key(&mut self, key: u8) {
+        self.bindings.retain(|(_, bound)| *bound != key);
+    }
+    pub fn get(&self, host: HostKey) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == host)
+            .map(|(_, key)| *key)
+    }
+    /// The keypad key pressed by a host key at `scancode` typing `character`, preferring a
+    /// binding of its position.
+    pub fn key_for(&self, scancode: Option<u16>, character: Option<char>) -> Option<u8> {
+        scancode
+            .and_then(|scancode| self.get(HostKey::Scancode(scancode)))
+            .or_else(|| character.and_then(|character| self.get(HostKey::from_char(character))))
+    }
+    /// The host keys bound to keypad `key`.
+    pub fn keys_for(&self, key: u8) -> impl Iterator<Item = HostKey> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == key)
+            .map(|(host, _)| *host)
+    }
+    pub fn bindings(&self) -> impl Iterator<Item = (HostKey, u8)> + '_ {
+        self.bindings.iter().copied()
+    }
+}
+
+impl Default for KeyMap {
+    /// The keys at the positions of the `1234`/`QWER`/`ASDF`/`ZXCV` block, whatever the layout.
+    fn default() -> Self {
+        let mut map = Self::empty();
+        let codes = [30, 31, 32, 33, 20, 26, 8, 21, 4, 22, 7, 9, 29, 27, 6, 25];
+        for (code, key) in codes.into_iter().zip(KEYPAD) {
+            map.bind(HostKey::Scancode(code), key);
+        }
+        map
+    }
+}
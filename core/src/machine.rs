@@ -0,0 +1,239 @@
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::*;
+
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 700;
+/// Longest stretch of wall-clock time emulated in one call, so that a stalled host (a hidden
+/// browser tab, a debugger break) does not make the machine race to catch up.
+const MAX_ELAPSED: Duration = Duration::from_millis(250);
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// What happened while the machine advanced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameResult {
+    /// The display changed and should be redrawn.
+    pub display_dirty: bool,
+    /// The sound timer is running, so the buzzer should be on.
+    pub sound_on: bool,
+    /// The program is blocked on `FX0A` until a key is pressed.
+    pub waiting_for_key: bool,
+    /// Set when the debugger paused execution.
+    pub stop: Option<StopReason>,
+}
+
+/// Runs a `CPU` at a fixed instruction rate with its timers counting down at exactly 60 Hz.
+///
+/// Time is measured in executed instructions: the timers tick once every
+/// `instructions_per_second / 60` instructions, whether the machine is driven by wall-clock time
+/// through `run_for` or by instruction count through `run_cycles`. Execution goes through a
+/// `Debugger`, which runs freely until breakpoints are set or it is paused.
+///
+/// Key events queued with `push_key_event` reach the CPU in order at the start of each timer
+/// frame. A key released in the same frame it was pressed is only released in the next one, so
+/// the program gets to see even the shortest tap.
+///
+/// Once a sample rate is set, the buzzer of every timer frame is also turned into audio that
+/// frontends pull with `fill_audio`, so sound stays in step with emulation.
+pub struct Machine {
+    cpu: CPU,
+    debugger: Debugger,
+    instructions_per_second: u32,
+    /// Progress towards the next timer tick, in sixtieths of an instruction. It reaches past the
+    /// tick when `run_frame` stops inside an instruction that spans several frames.
+    timer_phase: u64,
+    /// Wall-clock time not yet converted into whole instructions, in nanoseconds times the rate.
+    pending_time: u128,
+    key_events: VecDeque<KeyEvent>,
+    /// Keys pressed since the last timer tick, one bit per key.
+    pressed_this_frame: u16,
+    #[cfg(feature = "std")]
+    audio: AudioSynth,
+    frames: u64,
+}
+
+impl Machine {
+    pub fn new(cpu: CPU, instructions_per_second: u32) -> Self {
+        Self {
+            cpu,
+            debugger: Debugger::new(),
+            instructions_per_second: instructions_per_second.max(1),
+            timer_phase: 0,
+            pending_time: 0,
+            key_events: VecDeque::new(),
+            pressed_this_frame: 0,
+            #[cfg(feature = "std")]
+            audio: AudioSynth::new(),
+            frames: 0,
+        }
+    }
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+    pub fn step_into(&mut self) -> Result<StopReason, CpuError> {
+        self.debugger.step_into(&mut self.cpu)
+    }
+    pub fn step_over(&mut self) -> Result<Option<StopReason>, CpuError> {
+        self.debugger.step_over(&mut self.cpu)
+    }
+    pub fn step_out(&mut self) {
+        self.debugger.step_out(&self.cpu);
+    }
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second.max(1);
+        self.timer_phase = 0;
+        self.pending_time = 0;
+    }
+    /// Resets the CPU, the machine's notion of time and the debugger to its start mode.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.debugger.reset();
+        self.timer_phase = 0;
+        self.pending_time = 0;
+        self.key_events.clear();
+        self.pressed_this_frame = 0;
+        #[cfg(feature = "std")]
+        self.audio.clear();
+        self.frames = 0;
+    }
+    /// Number of timer ticks since the machine was created or reset.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+    /// Queues a key event, keeping the queue sorted by timestamp.
+    pub fn push_key_event(&mut self, event: KeyEvent) -> Result<(), CpuError> {
+        if event.key as usize >= NUM_KEYS {
+            return Err(CpuError::InvalidKey {
+                value: event.key as usize,
+            });
+        }
+        let position = self
+            .key_events
+            .iter()
+            .rposition(|queued| queued.timestamp <= event.timestamp)
+            .map_or(0, |idx| idx + 1);
+        self.key_events.insert(position, event);
+        Ok(())
+    }
+    /// Advances by `elapsed` wall-clock time, executing as many instructions as the rate allows.
+    pub fn run_for(&mut self, elapsed: Duration) -> Result<FrameResult, CpuError> {
+        self.apply_key_events();
+        if self.debugger.is_paused() {
+            return Ok(self.frame_result(None));
+        }
+        let elapsed = elapsed.min(MAX_ELAPSED).as_nanos();
+        self.pending_time += elapsed * self.instructions_per_second as u128;
+        let cycles = self.pending_time / NANOS_PER_SECOND;
+        self.pending_time %= NANOS_PER_SECOND;
+        self.run_cycles(cycles as u64)
+    }
+    /// Executes exactly `cycles` instructions unless the debugger stops earlier.
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<FrameResult, CpuError> {
+        self.run(cycles, u64::MAX)
+    }
+    /// Executes the instructions up to and including the next timer tick, one sixtieth of a
+    /// second of emulated time. Below 60 instructions per second an instruction spans several
+    /// frames, and the frames after the first pass without executing anything.
+    pub fn run_frame(&mut self) -> Result<FrameResult, CpuError> {
+        self.run(u64::MAX, 1)
+    }
+    fn run(&mut self, cycles: u64, max_frames: u64) -> Result<FrameResult, CpuError> {
+        let rate = self.instructions_per_second as u64;
+        let mut remaining = cycles;
+        let mut frames = 0;
+        let mut stop = None;
+        self.apply_key_events();
+        while remaining > 0 && frames < max_frames {
+            let batch = remaining.min(self.cycles_until_timer());
+            if batch > 0 {
+                let (executed, reason) = self.debugger.run(&mut self.cpu, batch as usize)?;
+                remaining -= executed as u64;
+                self.timer_phase += executed as u64 * TIMER_FREQUENCY as u64;
+                stop = reason;
+            }
+            while self.timer_phase >= rate && frames < max_frames {
+                self.timer_phase -= rate;
+                #[cfg(feature = "std")]
+                self.audio.push_frame(&self.cpu);
+                self.cpu.tick_timers();
+                self.frames += 1;
+                frames += 1;
+                self.pressed_this_frame = 0;
+                self.apply_key_events();
+            }
+            if stop.is_some() || self.debugger.is_paused() {
+                break;
+            }
+        }
+        let stop = stop.or_else(|| self.debugger.end_frame());
+        Ok(self.frame_result(stop))
+    }
+    fn apply_key_events(&mut self) {
+        while let Some(event) = self.key_events.front().copied() {
+            let bit = 1 << event.key;
+            if !event.pressed && self.pressed_this_frame & bit != 0 {
+                break;
+            }
+            self.key_events.pop_front();
+            if event.pressed {
+                self.pressed_this_frame |= bit;
+            }
+            // The key was checked when it was queued.
+            let _ = self.cpu.keypress(event.key as usize, event.pressed);
+        }
+    }
+    /// Instructions left until the next timer tick, 0 if an instruction already ran past it.
+    fn cycles_until_timer(&self) -> u64 {
+        let rate = self.instructions_per_second as u64;
+        rate.saturating_sub(self.timer_phase)
+            .div_ceil(TIMER_FREQUENCY as u64)
+    }
+    fn frame_result(&mut self, stop: Option<StopReason>) -> FrameResult {
+        FrameResult {
+            display_dirty: self.cpu.take_display_dirty(),
+            sound_on: self.cpu.is_sound_active(),
+            waiting_for_key: self.cpu.is_waiting_for_key(),
+            stop,
+        }
+    }
+}
+
+/// Audio rendering needs floating point maths from `std`.
+#[cfg(feature = "std")]
+impl Machine {
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate()
+    }
+    /// Starts producing audio at `sample_rate` samples per second, or stops when it is 0. Rates
+    /// from 1 to 59, less than a sample per timer frame, are rejected.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), CpuError> {
+        self.audio.set_sample_rate(sample_rate)
+    }
+    pub fn tone(&self) -> Tone {
+        self.audio.tone
+    }
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.audio.tone = tone;
+    }
+    /// Number of samples emulated but not yet taken with `fill_audio`.
+    pub fn queued_audio_samples(&self) -> usize {
+        self.audio.queued_samples()
+    }
+    /// Fills `out` with mono samples in `-1.0..=1.0`, oldest first. Once the emulated audio runs
+    /// out the rest fades to silence.
+    pub fn fill_audio(&mut self, out: &mut [f32]) {
+        self.audio.fill(out);
+    }
+}
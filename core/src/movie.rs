@@ -0,0 +1,261 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::savestate::{self, Reader};
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u8 = 1;
+const PRESSED_BIT: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct MovieEvent {
+    frame: u64,
+    key: u8,
+    pressed: bool,
+}
+
+/// An input recording that replays a run of a ROM exactly.
+///
+/// Besides the seed, ROM hash, variant, quirks and instruction rate the run started with, a
+/// movie holds every key change with the timer frame it was made in and a hash of the machine
+/// state at the end of each frame, which playback compares against to catch desyncs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Movie {
+    pub seed: u64,
+    pub rom_hash: u64,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub instructions_per_second: u32,
+    events: Vec<MovieEvent>,
+    hashes: Vec<u32>,
+}
+
+impl Movie {
+    /// Number of frames recorded.
+    pub fn frames(&self) -> u64 {
+        self.hashes.len() as u64
+    }
+    /// Puts a machine with the movie's ROM loaded back into the state recording started from,
+    /// with the ROM image reloaded over whatever the machine has run since.
+    pub fn prepare(&self, machine: &mut Machine) -> Result<(), CpuError> {
+        if machine.cpu().rom_hash != self.rom_hash {
+            return Err(CpuError::MovieRomMismatch);
+        }
+        let cpu = machine.cpu_mut();
+        cpu.set_variant(self.variant);
+        cpu.set_quirks(self.quirks);
+        cpu.restart();
+        cpu.rng = rng::seeded(self.seed);
+        machine.set_instructions_per_second(self.instructions_per_second);
+        machine.reset();
+        Ok(())
+    }
+    /// Serializes the movie. Key changes take two bytes or so each and frames four bytes for
+    /// the state hash, so an hour of play stays under a megabyte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(32 + self.events.len() * 2 + self.hashes.len() * 4);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.push(savestate::variant_to_byte(self.variant));
+        data.push(savestate::quirks_to_bits(self.quirks));
+        data.extend_from_slice(&self.instructions_per_second.to_le_bytes());
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(self.hashes.len() as u32).to_le_bytes());
+        let mut frame = 0;
+        for event in &self.events {
+            write_varint(&mut data, event.frame - frame);
+            frame = event.frame;
+            data.push(event.key | if event.pressed { PRESSED_BIT } else { 0 });
+        }
+        for hash in &self.hashes {
+            data.extend_from_slice(&hash.to_le_bytes());
+        }
+        data
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CpuError> {
+        Self::read(&mut Reader { data }).map_err(|_| CpuError::InvalidMovie)
+    }
+    fn read(reader: &mut Reader) -> Result<Self, CpuError> {
+        if reader.bytes(MAGIC.len())? != MAGIC || reader.u8()? != VERSION {
+            return Err(CpuError::InvalidMovie);
+        }
+        let variant = savestate::variant_from_byte(reader.u8()?)?;
+        let quirks = savestate::quirks_from_bits(reader.u8()?);
+        let instructions_per_second = reader.u32()?;
+        let seed = reader.u64()?;
+        let rom_hash = reader.u64()?;
+        let event_count = reader.u32()? as usize;
+        let frame_count = reader.u32()? as usize;
+        let mut events = Vec::with_capacity(event_count.min(reader.data.len() / 2));
+        let mut frame = 0u64;
+        for _ in 0..event_count {
+            frame = frame
+                .checked_add(read_varint(reader)?)
+                .ok_or(CpuError::InvalidMovie)?;
+            let byte = reader.u8()?;
+            let key = byte & !PRESSED_BIT;
+            if key as usize >= NUM_KEYS {
+                return Err(CpuError::InvalidMovie);
+            }
+            events.push(MovieEvent {
+                frame,
+                key,
+                pressed: byte & PRESSED_BIT != 0,
+            });
+        }
+        if reader.data.len() != frame_count * 4 {
+            return Err(CpuError::InvalidMovie);
+        }
+        let mut hashes = Vec::with_capacity(frame_count);
+        for _ in 0..frame_count {
+            hashes.push(reader.u32()?);
+        }
+        Ok(Self {
+            seed,
+            rom_hash,
+            variant,
+            quirks,
+            instructions_per_second,
+            events,
+            hashes,
+        })
+    }
+}
+
+/// Records the input given to a machine, frame by frame, into a `Movie`.
+///
+/// Keys must go through `push_key` and frames through `run_frame` for the recording to replay.
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts recording a machine with its ROM loaded. The machine is restarted from the ROM image
+    /// and `CXNN` switched to the default generator seeded with `seed`.
+    pub fn new(machine: &mut Machine, seed: u64) -> Self {
+        let cpu = machine.cpu();
+        let movie = Movie {
+            seed,
+            rom_hash: cpu.rom_hash,
+            variant: cpu.variant(),
+            quirks: cpu.quirks(),
+            instructions_per_second: machine.instructions_per_second(),
+            events: Vec::new(),
+            hashes: Vec::new(),
+        };
+        // The ROM hash was taken from this machine.
+        let _ = movie.prepare(machine);
+        Self { movie }
+    }
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+    /// Records a key change and queues it for the current frame.
+    pub fn push_key(
+        &mut self,
+        machine: &mut Machine,
+        key: u8,
+        pressed: bool,
+    ) -> Result<(), CpuError> {
+        let frame = machine.frame_count();
+        machine.push_key_event(KeyEvent::new(key, pressed, frame_timestamp(frame)))?;
+        self.movie.events.push(MovieEvent {
+            frame,
+            key,
+            pressed,
+        });
+        Ok(())
+    }
+    /// Runs the machine up to the next timer tick and records the state it ended in.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<FrameResult, CpuError> {
+        let result = machine.run_frame()?;
+        if machine.frame_count() > self.movie.frames() {
+            self.movie.hashes.push(state_hash(machine.cpu()));
+        }
+        Ok(result)
+    }
+}
+
+/// Replays a `Movie`, feeding its key changes to the machine on the frames they were recorded.
+pub struct MoviePlayer {
+    movie: Movie,
+    next_event: usize,
+}
+
+impl MoviePlayer {
+    /// Prepares `machine`, which must have the movie's ROM loaded, for playback.
+    pub fn new(movie: Movie, machine: &mut Machine) -> Result<Self, CpuError> {
+        movie.prepare(machine)?;
+        Ok(Self {
+            movie,
+            next_event: 0,
+        })
+    }
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+    /// Whether every recorded frame has been played.
+    pub fn is_finished(&self, machine: &Machine) -> bool {
+        machine.frame_count() >= self.movie.frames()
+    }
+    /// Runs the machine up to the next timer tick with the recorded input and fails with
+    /// `CpuError::Desync` if the state it ends in differs from the recording.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<FrameResult, CpuError> {
+        let frame = machine.frame_count();
+        while let Some(event) = self.movie.events.get(self.next_event) {
+            if event.frame > frame {
+                break;
+            }
+            let timestamp = frame_timestamp(event.frame);
+            machine.push_key_event(KeyEvent::new(event.key, event.pressed, timestamp))?;
+            self.next_event += 1;
+        }
+        let result = machine.run_frame()?;
+        if machine.frame_count() > frame {
+            match self.movie.hashes.get(frame as usize) {
+                Some(hash) if *hash != state_hash(machine.cpu()) => {
+                    return Err(CpuError::Desync { frame });
+                }
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn frame_timestamp(frame: u64) -> Duration {
+    Duration::from_secs(frame) / TIMER_FREQUENCY
+}
+
+fn state_hash(cpu: &CPU) -> u32 {
+    let hash = savestate::fnv1a(&cpu.save_state());
+    (hash ^ (hash >> 32)) as u32
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(reader: &mut Reader) -> Result<u64, CpuError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.u8()?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(CpuError::InvalidMovie)
+}
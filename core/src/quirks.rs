@@ -0,0 +1,89 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift `Vx` in place instead of shifting `Vy` into `Vx`.
+    pub shift: bool,
+    /// `FX55`/`FX65` leave `I` pointing past the last register transferred.
+    pub memory_increment: bool,
+    /// `BNNN` is read as `BXNN` and jumps to `XNN + Vx` instead of `NNN + V0`.
+    pub jump: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset `VF` to 0.
+    pub vf_reset: bool,
+    /// `DXYN` clips sprites at the screen edges instead of wrapping them around.
+    pub clipping: bool,
+    /// `DXYN` waits for the next timer tick before drawing.
+    pub display_wait: bool,
+    /// `FX0A` completes when the pressed key is released rather than as soon as it is held.
+    pub key_release: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        memory_increment: true,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+        key_release: true,
+    };
+    pub const CHIP_48: Quirks = Quirks {
+        shift: true,
+        memory_increment: true,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        key_release: true,
+    };
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        memory_increment: false,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+        key_release: true,
+    };
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        memory_increment: true,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+        key_release: true,
+    };
+    /// Names accepted by `profile`.
+    pub const PROFILE_NAMES: [&'static str; 5] =
+        ["default", "cosmac-vip", "chip-48", "schip", "xochip"];
+    /// Looks up a quirks profile by name.
+    pub fn profile(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip-48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// The behaviour the interpreter has always had: in-place shifts, `I` untouched by
+    /// `FX55`/`FX65`, `BNNN` relative to `V0`, sprites that wrap around the screen and `FX0A`
+    /// completing as soon as a key is pressed, though not with a key still held from the previous
+    /// `FX0A`. The other profiles wait for the release.
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            memory_increment: false,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+            key_release: false,
+        }
+    }
+}
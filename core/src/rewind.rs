@@ -0,0 +1,164 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::{CpuError, CPU};
+
+/// Ring buffer of machine snapshots for stepping emulation backwards.
+///
+/// Only the newest snapshot is kept whole. Every older one is stored as the difference to its
+/// successor, XORed and run-length encoded, so the few bytes that change per frame are all that
+/// history costs. The oldest snapshots are dropped once the budget is exceeded.
+pub struct RewindBuffer {
+    budget: usize,
+    interval: u32,
+    frames_since_snapshot: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl RewindBuffer {
+    /// `budget` is the memory allowed for history in bytes, `interval` the number of frames
+    /// between snapshots.
+    pub fn new(budget: usize, interval: u32) -> Self {
+        Self {
+            budget,
+            interval: interval.max(1),
+            frames_since_snapshot: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+    /// Number of frames that can currently be rewound.
+    pub fn available_frames(&self) -> usize {
+        self.deltas.len() * self.interval as usize
+    }
+    pub fn clear(&mut self) {
+        self.frames_since_snapshot = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+    /// Called once per emulated frame, takes a snapshot every `interval` frames.
+    pub fn record(&mut self, cpu: &CPU) {
+        self.frames_since_snapshot += 1;
+        if self.latest.is_some() && self.frames_since_snapshot < self.interval {
+            return;
+        }
+        self.frames_since_snapshot = 0;
+        let state = cpu.save_state();
+        match self.latest.take() {
+            Some(previous) if previous.len() == state.len() => {
+                let delta = encode_delta(&previous, &state);
+                self.deltas_size += delta.len();
+                self.deltas.push_back(delta);
+            }
+            _ => self.clear(),
+        }
+        self.latest = Some(state);
+        while self.deltas_size + self.latest_size() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+    /// Moves `cpu` back by up to `frames` frames, rounded up to whole snapshots, and returns the
+    /// number of frames actually rewound.
+    pub fn rewind(&mut self, cpu: &mut CPU, frames: usize) -> Result<usize, CpuError> {
+        let interval = self.interval as usize;
+        let steps = frames.div_ceil(interval).min(self.deltas.len());
+        if steps == 0 {
+            return Ok(0);
+        }
+        let mut state = match self.latest.take() {
+            Some(state) => state,
+            None => return Ok(0),
+        };
+        for _ in 0..steps {
+            if let Some(delta) = self.deltas.pop_back() {
+                self.deltas_size -= delta.len();
+                apply_delta(&mut state, &delta);
+            }
+        }
+        let result = cpu.load_state(&state);
+        self.latest = Some(state);
+        self.frames_since_snapshot = 0;
+        result.map(|_| steps * interval)
+    }
+    fn latest_size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len)
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = data.get(*pos) {
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+/// Encodes `older XOR newer` as alternating runs of unchanged byte counts and literal XOR bytes.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < newer.len() {
+        let unchanged = older[pos..]
+            .iter()
+            .zip(&newer[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        let start = pos + unchanged;
+        let changed = older[start..]
+            .iter()
+            .zip(&newer[start..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        push_varint(&mut out, unchanged);
+        push_varint(&mut out, changed);
+        out.extend(
+            older[start..start + changed]
+                .iter()
+                .zip(&newer[start..start + changed])
+                .map(|(a, b)| a ^ b),
+        );
+        pos = start + changed;
+    }
+    out
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut offset = 0;
+    while pos < delta.len() {
+        offset += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for (byte, mask) in state[offset..offset + changed]
+            .iter_mut()
+            .zip(&delta[pos..pos + changed])
+        {
+            *byte ^= mask;
+        }
+        pos += changed;
+        offset += changed;
+    }
+}
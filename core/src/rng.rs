@@ -0,0 +1,109 @@
+#[cfg(feature = "rand")]
+use rand::RngCore;
+
+pub const DEFAULT_SEED: u64 = 0x0C8_5EED;
+
+/// The CPU's generator: replaceable with `set_rng` when boxing is available, the default
+/// generator otherwise.
+#[cfg(feature = "alloc")]
+pub(crate) type CpuRng = alloc::boxed::Box<dyn Rng>;
+#[cfg(not(feature = "alloc"))]
+pub(crate) type CpuRng = XorShiftRng;
+
+#[cfg(feature = "alloc")]
+pub(crate) fn seeded(seed: u64) -> CpuRng {
+    alloc::boxed::Box::new(XorShiftRng::new(seed))
+}
+
+#[cfg(not(feature = "alloc"))]
+pub(crate) fn seeded(seed: u64) -> CpuRng {
+    XorShiftRng::new(seed)
+}
+
+/// Puts `rng` in a state returned by `Rng::state`, switching to the default generator if `rng`
+/// cannot be restored.
+pub(crate) fn restore(rng: &mut CpuRng, state: u64) {
+    if rng.state().is_some() {
+        rng.set_state(state);
+    } else {
+        let mut restored = seeded(DEFAULT_SEED);
+        restored.set_state(state);
+        *rng = restored;
+    }
+}
+
+/// Source of the random bytes `CXNN` masks with `NN`.
+pub trait Rng {
+    fn next_byte(&mut self) -> u8;
+    /// The generator's internal state, which save states keep so that `CXNN` carries on with the
+    /// same numbers after a load. Generators that cannot be restored return `None`.
+    fn state(&self) -> Option<u64> {
+        None
+    }
+    /// Restores a state returned by `state`.
+    fn set_state(&mut self, _state: u64) {}
+}
+
+/// Small deterministic xorshift64* generator. The same seed always produces the same sequence,
+/// on every platform and build, which keeps runs reproducible.
+#[derive(Debug, Clone)]
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed with a SplitMix64 step so that small or zero seeds still give a
+        // well-mixed, non-zero state.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { DEFAULT_SEED } else { z },
+        }
+    }
+}
+
+impl Default for XorShiftRng {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl Rng for XorShiftRng {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+    fn state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+    fn set_state(&mut self, state: u64) {
+        self.state = if state == 0 { DEFAULT_SEED } else { state };
+    }
+}
+
+/// Non-reproducible randomness from the operating system, for frontends where runs should
+/// differ.
+#[cfg(feature = "rand")]
+#[derive(Debug, Clone, Default)]
+pub struct EntropyRng {
+    rng: rand::rngs::ThreadRng,
+}
+
+#[cfg(feature = "rand")]
+impl EntropyRng {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "rand")]
+impl Rng for EntropyRng {
+    fn next_byte(&mut self) -> u8 {
+        self.rng.next_u32() as u8
+    }
+}
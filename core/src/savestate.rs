@@ -0,0 +1,232 @@
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"C8SV";
+const VERSION: u8 = 1;
+const NO_KEY: u8 = 0xFF;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn quirks_to_bits(quirks: Quirks) -> u8 {
+    [
+        quirks.shift,
+        quirks.memory_increment,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+        quirks.key_release,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (idx, set)| bits | ((*set as u8) << idx))
+}
+
+pub(crate) fn quirks_from_bits(bits: u8) -> Quirks {
+    Quirks {
+        shift: bits & 0b00_0001 != 0,
+        memory_increment: bits & 0b00_0010 != 0,
+        jump: bits & 0b00_0100 != 0,
+        vf_reset: bits & 0b00_1000 != 0,
+        clipping: bits & 0b01_0000 != 0,
+        display_wait: bits & 0b10_0000 != 0,
+        key_release: bits & 0b100_0000 != 0,
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn variant_to_byte(variant: Variant) -> u8 {
+    match variant {
+        Variant::Chip8 => 0,
+        #[cfg(feature = "schip")]
+        Variant::SuperChip => 1,
+        #[cfg(feature = "xochip")]
+        Variant::XoChip => 2,
+    }
+}
+
+pub(crate) fn variant_from_byte(byte: u8) -> Result<Variant, CpuError> {
+    match byte {
+        0 => Ok(Variant::Chip8),
+        #[cfg(feature = "schip")]
+        1 => Ok(Variant::SuperChip),
+        #[cfg(feature = "xochip")]
+        2 => Ok(Variant::XoChip),
+        _ => Err(CpuError::InvalidSaveState),
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], CpuError> {
+        if self.data.len() < len {
+            return Err(CpuError::InvalidSaveState);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, CpuError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn bool(&mut self) -> Result<bool, CpuError> {
+        Ok(self.u8()? != 0)
+    }
+    pub(crate) fn u16(&mut self) -> Result<u16, CpuError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    #[cfg(feature = "alloc")]
+    pub(crate) fn u32(&mut self) -> Result<u32, CpuError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+    pub(crate) fn u64(&mut self) -> Result<u64, CpuError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl CPU {
+    /// Serializes the machine state, excluding host key state, into a versioned binary blob
+    /// tagged with the hash of the loaded ROM and the size of the frame buffer, which depends on
+    /// the `schip` feature. The state of the `CXNN` generator is included when it can be
+    /// restored.
+    #[cfg(feature = "alloc")]
+    pub fn save_state(&self) -> alloc::vec::Vec<u8> {
+        let memory = &self.memory[..self.variant.memory_size()];
+        let mut state = alloc::vec::Vec::with_capacity(memory.len() + FRAME_BUFFER_SIZE + 128);
+        state.extend_from_slice(MAGIC);
+        state.push(VERSION);
+        state.extend_from_slice(&self.rom_hash.to_le_bytes());
+        state.extend_from_slice(&(FRAME_BUFFER_WIDTH as u16).to_le_bytes());
+        state.extend_from_slice(&(FRAME_BUFFER_HEIGHT as u16).to_le_bytes());
+        state.push(variant_to_byte(self.variant));
+        state.push(quirks_to_bits(self.quirks));
+        state.extend_from_slice(memory);
+        state.extend_from_slice(&self.data_registers);
+        state.extend_from_slice(&self.address_register.to_le_bytes());
+        state.extend_from_slice(&self.program_counter.to_le_bytes());
+        for address in self.stack {
+            state.extend_from_slice(&address.to_le_bytes());
+        }
+        state.push(self.stack_pointer);
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.push(self.vblank as u8);
+        state.push(self.hires as u8);
+        state.push(self.halted as u8);
+        state.push(self.plane_mask);
+        state.extend_from_slice(&self.rpl_flags);
+        state.extend_from_slice(&self.audio_pattern);
+        state.push(self.pitch);
+        state.extend_from_slice(&self.frame_buffer);
+        state.push(self.waiting_for_key as u8);
+        state.push(self.key_wait.unwrap_or(NO_KEY));
+        match self.rng.state() {
+            Some(rng_state) => {
+                state.push(1);
+                state.extend_from_slice(&rng_state.to_le_bytes());
+            }
+            None => state.push(0),
+        }
+        state
+    }
+    /// Restores a state produced by `save_state`. The state is rejected without touching the
+    /// machine if it is malformed, was saved while a different ROM was loaded or by a build
+    /// with a different frame buffer.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), CpuError> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(CpuError::InvalidSaveState);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(CpuError::UnsupportedSaveStateVersion { version });
+        }
+        if reader.u64()? != self.rom_hash {
+            return Err(CpuError::SaveStateRomMismatch);
+        }
+        let (width, height) = (reader.u16()?, reader.u16()?);
+        if (width as u32, height as u32) != (FRAME_BUFFER_WIDTH, FRAME_BUFFER_HEIGHT) {
+            return Err(CpuError::SaveStateDisplayMismatch { width, height });
+        }
+        let variant = variant_from_byte(reader.u8()?)?;
+        let quirks = quirks_from_bits(reader.u8()?);
+        let memory = reader.bytes(variant.memory_size())?;
+        let data_registers = reader.bytes(NUM_DATA_REGISTERS)?;
+        let address_register = reader.u16()?;
+        let program_counter = reader.u16()?;
+        let mut stack = [0; STACK_LENGTH];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let stack_pointer = reader.u8()?;
+        if stack_pointer as usize > STACK_LENGTH {
+            return Err(CpuError::InvalidSaveState);
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let vblank = reader.bool()?;
+        let hires = reader.bool()?;
+        let halted = reader.bool()?;
+        let plane_mask = reader.u8()? & 0b11;
+        let rpl_flags = reader.bytes(NUM_RPL_FLAGS)?;
+        let audio_pattern = reader.bytes(AUDIO_PATTERN_SIZE)?;
+        let pitch = reader.u8()?;
+        let frame_buffer = reader.bytes(FRAME_BUFFER_SIZE)?;
+        let waiting_for_key = reader.bool()?;
+        let key_wait = match reader.u8()? {
+            NO_KEY => None,
+            key if (key as usize) < NUM_KEYS => Some(key),
+            _ => return Err(CpuError::InvalidSaveState),
+        };
+        let rng_state = if reader.bool()? {
+            Some(reader.u64()?)
+        } else {
+            None
+        };
+        if !reader.data.is_empty() {
+            return Err(CpuError::InvalidSaveState);
+        }
+
+        self.variant = variant;
+        self.quirks = quirks;
+        self.memory[..memory.len()].copy_from_slice(memory);
+        self.data_registers.copy_from_slice(data_registers);
+        self.address_register = address_register;
+        self.program_counter = program_counter;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.vblank = vblank;
+        self.hires = hires;
+        self.halted = halted;
+        self.plane_mask = plane_mask;
+        self.rpl_flags.copy_from_slice(rpl_flags);
+        self.audio_pattern.copy_from_slice(audio_pattern);
+        self.pitch = pitch;
+        self.frame_buffer.copy_from_slice(frame_buffer);
+        self.display_dirty = true;
+        self.waiting_for_key = waiting_for_key;
+        self.key_wait = key_wait;
+        if let Some(rng_state) = rng_state {
+            rng::restore(&mut self.rng, rng_state);
+        }
+        self.flush_instruction_cache();
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::disasm::Instruction;
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// Aligned columns with the disassembled instruction, for reading.
+    #[default]
+    Text,
+    /// Space separated hex fields without the mnemonic, for diffing against other emulators.
+    Compact,
+}
+
+/// Records every instruction the CPU executes, together with the machine state before it ran.
+///
+/// Attach one with `CPU::set_tracer`. In ring buffer mode only the last instructions are kept
+/// and written out when `CPU::tick` fails.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    range: Option<RangeInclusive<u16>>,
+    ring: Option<(usize, VecDeque<String>)>,
+    cycle: u64,
+    io_error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer,
+            format: TraceFormat::Text,
+            range: None,
+            ring: None,
+            cycle: 0,
+            io_error: None,
+        }
+    }
+    pub fn with_format(mut self, format: TraceFormat) -> Self {
+        self.format = format;
+        self
+    }
+    /// Only traces instructions whose address lies in `range`.
+    pub fn with_pc_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = Some(range);
+        self
+    }
+    /// Keeps only the last `capacity` instructions, which are written out on error.
+    pub fn with_ring_buffer(mut self, capacity: usize) -> Self {
+        self.ring = Some((capacity.max(1), VecDeque::with_capacity(capacity)));
+        self
+    }
+    /// Number of instructions executed since the tracer was attached.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+    /// Flushes the writer and reports the first I/O error hit while tracing.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.io_error.take() {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+    pub(crate) fn record(&mut self, cpu: &CPU) {
+        self.cycle += 1;
+        let pc = cpu.program_counter;
+        if matches!(&self.range, Some(range) if !range.contains(&pc)) {
+            return;
+        }
+        let line = self.format_line(cpu);
+        match &mut self.ring {
+            Some((capacity, lines)) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            None => self.write_line(&line),
+        }
+    }
+    /// Writes out the ring buffer, if any, followed by `err`.
+    pub(crate) fn dump(&mut self, err: &CpuError) {
+        if let Some((_, lines)) = &mut self.ring {
+            let lines: Vec<String> = lines.drain(..).collect();
+            for line in &lines {
+                self.write_line(line);
+            }
+        }
+        self.write_line(&format!("error: {}", err));
+    }
+    fn write_line(&mut self, line: &str) {
+        if self.io_error.is_none() {
+            if let Err(err) = writeln!(self.writer, "{}", line) {
+                self.io_error = Some(err);
+            }
+        }
+    }
+    fn format_line(&self, cpu: &CPU) -> String {
+        let pc = cpu.program_counter as usize;
+        let memory = &cpu.memory[..cpu.variant.memory_size()];
+        let word = |addr: usize| {
+            u16::from_be_bytes([
+                memory.get(addr).copied().unwrap_or(0),
+                memory.get(addr + 1).copied().unwrap_or(0),
+            ])
+        };
+        let opcode = word(pc);
+        let mut line = String::with_capacity(128);
+        match self.format {
+            TraceFormat::Text => {
+                let mnemonic =
+                    Instruction::decode_for(opcode, word(pc + 2), cpu.variant).to_string();
+                let _ = write!(
+                    line,
+                    "{:>10}  {:04X}  {:04X}  {:<24}",
+                    self.cycle, pc, opcode, mnemonic
+                );
+                for (idx, value) in cpu.data_registers.iter().enumerate() {
+                    let _ = write!(line, " V{:X}={:02X}", idx, value);
+                }
+                let _ = write!(
+                    line,
+                    " I={:04X} SP={:X} DT={:02X} ST={:02X}",
+                    cpu.address_register, cpu.stack_pointer, cpu.delay_timer, cpu.sound_timer
+                );
+            }
+            TraceFormat::Compact => {
+                let _ = write!(line, "{} {:04X} {:04X} ", self.cycle, pc, opcode);
+                for value in cpu.data_registers {
+                    let _ = write!(line, "{:02X}", value);
+                }
+                let _ = write!(
+                    line,
+                    " {:04X} {:02X} {:02X} {:02X}",
+                    cpu.address_register, cpu.stack_pointer, cpu.delay_timer, cpu.sound_timer
+                );
+            }
+        }
+        line
+    }
+}
@@ -0,0 +1,219 @@
+//! Runs the test ROMs in `roms/tests` under every quirks profile and compares the final screen
+//! with the golden bitmaps in `tests/golden`.
+//!
+//! Goldens are plain text, one character per pixel (`.` off, `#` on, `*` and `@` for the second
+//! and both XO-CHIP planes). A ROM has one golden, `<ROM>.txt`, that every profile must draw;
+//! `<ROM>.<profile>.txt` replaces it for a profile whose screen legitimately differs, such as
+//! the blinking cursor of `KEYPAD`, which display wait slows down. The goldens show what the
+//! Timendus suite documents as a pass: its logos, and a check mark for every result of `FLAGS`
+//! and `OPCODES`. `OPCODES.txt` is the screen of `resources/tests.png` pixel for pixel. After a
+//! deliberate change in output, regenerate them with
+//! `UPDATE_GOLDEN=1 cargo test --test conformance` and check the new screens by eye.
+//!
+//! `QUIRKS` needs no golden: it shows which quirks the interpreter has, and the expected screen
+//! is drawn from how the platform each profile is named after behaves, in `PLATFORM_QUIRKS`.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libchip8cpu::*;
+
+const FRAMES: usize = 600;
+const ROMS: [&str; 6] = ["CHIP8-LOGO", "IBM", "IBM2", "FLAGS", "OPCODES", "KEYPAD"];
+const PROFILES: [(&str, Variant, Option<Quirks>); 5] = [
+    ("default", Variant::Chip8, None),
+    ("cosmac-vip", Variant::Chip8, Some(Quirks::COSMAC_VIP)),
+    ("chip-48", Variant::Chip8, Some(Quirks::CHIP_48)),
+    ("schip", Variant::SuperChip, Some(Quirks::SUPER_CHIP)),
+    ("xochip", Variant::XoChip, Some(Quirks::XO_CHIP)),
+];
+const PIXELS: [char; 4] = ['.', '#', '*', '@'];
+/// What `QUIRKS` must find under each profile, in the order it probes them: `vf_reset`,
+/// `memory_increment`, `display_wait`, `clipping`, `shift` and `jump`. Written out from the
+/// behaviour of the real platforms rather than taken from `Quirks`.
+const PLATFORM_QUIRKS: [(&str, [bool; 6]); 5] = [
+    // This interpreter before quirks were configurable.
+    ("default", [false, false, false, false, true, false]),
+    ("cosmac-vip", [true, true, true, true, false, false]),
+    ("chip-48", [false, true, false, true, true, true]),
+    // SUPER-CHIP 1.1 leaves `I` alone and, in the modern reading, draws without waiting.
+    ("schip", [false, false, false, true, true, true]),
+    ("xochip", [false, true, false, false, false, false]),
+];
+
+fn rom_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms/tests")
+}
+
+fn golden_path(rom: &str, profile: Option<&str>) -> PathBuf {
+    let name = match profile {
+        Some(profile) => format!("{rom}.{profile}.txt"),
+        None => format!("{rom}.txt"),
+    };
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+}
+
+fn run(rom: &[u8], variant: Variant, quirks: Quirks) -> Result<String, CpuError> {
+    let mut cpu = CPU::new_with_variant(variant, quirks);
+    cpu.load(rom)?;
+    let mut machine = Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_SECOND);
+    for _ in 0..FRAMES {
+        machine.run_frame()?;
+    }
+    let display = machine.cpu().get_display();
+    let mut screen = String::new();
+    for row in display.pixels.chunks(display.width as usize) {
+        screen.extend(row.iter().map(|pixel| PIXELS[*pixel as usize & 0b11]));
+        screen.push('\n');
+    }
+    Ok(screen)
+}
+
+/// Draws the screen with `+` for pixels that are lit but should not be and `-` for pixels that
+/// should be lit but are not.
+fn diff(expected: &str, actual: &str) -> String {
+    let mut out = String::new();
+    let mut expected_rows = expected.lines();
+    for (y, actual_row) in actual.lines().enumerate() {
+        let expected_row = expected_rows.next().unwrap_or_default();
+        let mut expected_pixels = expected_row.chars();
+        let row: String = actual_row
+            .chars()
+            .map(|actual| match expected_pixels.next() {
+                Some(expected) if expected == actual => actual,
+                Some('.') | None => '+',
+                Some(_) => '-',
+            })
+            .collect();
+        let marker = if row == actual_row && expected_row.len() == actual_row.len() {
+            ' '
+        } else {
+            '>'
+        };
+        let _ = writeln!(out, "{marker}{y:3} {row}");
+    }
+    out
+}
+
+/// Checks `actual` against the profile's golden, or writes it when updating. The shared golden
+/// is written for the first profile and a profile's own only where its screen differs.
+fn check_golden(rom: &str, profile: &str, actual: &str, update: bool) -> Result<(), String> {
+    let own = golden_path(rom, Some(profile));
+    let shared = golden_path(rom, None);
+    if update {
+        match fs::read_to_string(&shared) {
+            Ok(expected) if expected == actual => {
+                let _ = fs::remove_file(&own);
+            }
+            Ok(_) if profile != PROFILES[0].0 => fs::write(&own, actual).unwrap(),
+            _ => {
+                fs::create_dir_all(shared.parent().unwrap()).unwrap();
+                fs::write(&shared, actual).unwrap();
+                let _ = fs::remove_file(&own);
+            }
+        }
+        return Ok(());
+    }
+    let path = if own.exists() { own } else { shared };
+    match fs::read_to_string(&path) {
+        Ok(expected) if expected == actual => Ok(()),
+        Ok(expected) => Err(diff(&expected, actual)),
+        Err(err) => Err(format!("unable to read {}: {err}", path.display())),
+    }
+}
+
+#[test]
+fn test_roms_match_golden_screens() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for rom_name in ROMS {
+        let rom = fs::read(rom_dir().join(rom_name)).expect("missing test rom");
+        for (profile, variant, quirks) in PROFILES {
+            let name = format!("{rom_name} ({profile})");
+            let result = run(&rom, variant, quirks.unwrap_or_default())
+                .map_err(|err| err.to_string())
+                .and_then(|actual| check_golden(rom_name, profile, &actual, update));
+            match result {
+                Ok(()) if update => println!("UPDATED {name}"),
+                Ok(()) => println!("ok   {name}"),
+                Err(err) => {
+                    println!("FAIL {name}");
+                    failures.push(format!("{name}:\n{err}"));
+                }
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} screen(s) differ from the goldens:\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+/// The screen `QUIRKS` draws when it finds the quirks in `found`: in two columns of three rows,
+/// the number of each quirk it probes followed by 1 or 0.
+fn quirks_screen(font: &[u8], found: [bool; 6]) -> String {
+    let width = SCREEN_WIDTH as usize;
+    let mut pixels = vec![false; width * SCREEN_HEIGHT as usize];
+    let mut draw = |digit: usize, x: usize, y: usize| {
+        for (row, byte) in font[digit * 5..digit * 5 + 5].iter().enumerate() {
+            for column in 0..8 {
+                pixels[(y + row) * width + x + column] ^= byte & (0x80 >> column) != 0;
+            }
+        }
+    };
+    for (idx, found) in found.into_iter().enumerate() {
+        let (x, y) = (8 + idx / 3 * 32, 4 + idx % 3 * 8);
+        draw(idx + 1, x, y);
+        draw(found as usize, x + 6, y);
+    }
+    let mut screen = String::new();
+    for row in pixels.chunks(width) {
+        screen.extend(row.iter().map(|pixel| PIXELS[*pixel as usize]));
+        screen.push('\n');
+    }
+    screen
+}
+
+#[test]
+fn quirks_rom_detects_each_profile() {
+    let source = fs::read_to_string(rom_dir().join("src/QUIRKS.s")).unwrap();
+    let rom = asm::assemble(&source).unwrap();
+    assert_eq!(
+        fs::read(rom_dir().join("QUIRKS")).unwrap(),
+        rom,
+        "roms/tests/QUIRKS is out of date with its source"
+    );
+    let mut failures = Vec::new();
+    for (profile, variant, quirks) in PROFILES {
+        let name = format!("QUIRKS ({profile})");
+        let quirks = quirks.unwrap_or_default();
+        let (_, found) = PLATFORM_QUIRKS
+            .iter()
+            .find(|(platform, _)| *platform == profile)
+            .expect("every profile has a row in PLATFORM_QUIRKS");
+        let font = CPU::new_with_variant(variant, quirks).memory()[..80].to_vec();
+        let expected = quirks_screen(&font, *found);
+        match run(&rom, variant, quirks) {
+            Ok(actual) if actual == expected => println!("ok   {name}"),
+            Ok(actual) => {
+                println!("FAIL {name}");
+                failures.push(format!("{name}:\n{}", diff(&expected, &actual)));
+            }
+            Err(err) => {
+                println!("FAIL {name}: {err}");
+                failures.push(format!("{name}: {err}"));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} profile(s) differ from the platform they are named after:\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,124 @@
+//! Stepping, breakpoints and watches on a small program with a subroutine call and an `FX55`.
+
+use libchip8cpu::*;
+
+const SOURCE: &str = "
+        LD V0, 1
+        CALL sub
+        LD V1, 2
+        LD I, buffer
+        LD [I], V1
+done:   JP done
+sub:    LD V2, 3
+        LD V3, 4
+        RET
+buffer: DB 0, 0
+";
+const CALL: u16 = 0x202;
+const AFTER_CALL: u16 = 0x204;
+const STORE: u16 = 0x208;
+const DONE: u16 = 0x20A;
+const SUB: u16 = 0x20C;
+const BUFFER: u16 = 0x212;
+
+fn machine() -> Machine {
+    let mut cpu = CPU::new();
+    cpu.load(&asm::assemble(SOURCE).unwrap()).unwrap();
+    Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_SECOND)
+}
+
+fn paused_machine() -> Machine {
+    let mut machine = machine();
+    machine.debugger_mut().pause();
+    machine
+}
+
+#[test]
+fn step_over_runs_a_call_as_one_step() {
+    let mut machine = paused_machine();
+    assert_eq!(machine.step_over().unwrap(), Some(StopReason::Step));
+    assert_eq!(machine.cpu().pc(), CALL);
+
+    assert_eq!(machine.step_over().unwrap(), None);
+    let stop = machine.run_frame().unwrap().stop;
+    assert_eq!(stop, Some(StopReason::StepOver));
+    assert_eq!(machine.cpu().pc(), AFTER_CALL);
+    assert_eq!(machine.cpu().registers()[2..4], [3, 4]);
+    assert!(machine.debugger().is_paused());
+}
+
+#[test]
+fn step_into_enters_the_call_and_step_out_leaves_it() {
+    let mut machine = paused_machine();
+    machine.step_into().unwrap();
+    assert_eq!(machine.step_into().unwrap(), StopReason::Step);
+    assert_eq!(machine.cpu().pc(), SUB);
+    assert_eq!(machine.cpu().sp(), 1);
+    machine.step_into().unwrap();
+
+    machine.step_out();
+    let stop = machine.run_frame().unwrap().stop;
+    assert_eq!(stop, Some(StopReason::StepOut));
+    assert_eq!(machine.cpu().pc(), AFTER_CALL);
+    assert_eq!(machine.cpu().sp(), 0);
+}
+
+#[test]
+fn breakpoints_stop_before_the_instruction_and_resume_past_it() {
+    let mut machine = machine();
+    machine.debugger_mut().add_breakpoint(SUB);
+    let stop = machine.run_frame().unwrap().stop;
+    assert_eq!(stop, Some(StopReason::Breakpoint { pc: SUB }));
+    assert_eq!(machine.cpu().pc(), SUB);
+
+    machine.debugger_mut().resume();
+    assert_eq!(machine.run_frame().unwrap().stop, None);
+    assert_eq!(machine.cpu().pc(), DONE);
+}
+
+#[test]
+fn write_watchpoints_stop_after_fx55() {
+    let mut machine = machine();
+    machine
+        .debugger_mut()
+        .add_watchpoint(BUFFER + 1..=BUFFER + 4, Access::Write);
+    let stop = machine.run_frame().unwrap().stop;
+    assert_eq!(
+        stop,
+        Some(StopReason::Watchpoint {
+            pc: STORE,
+            addr: BUFFER + 1,
+            access: Access::Write,
+        })
+    );
+    assert_eq!(machine.cpu().pc(), DONE);
+    assert_eq!(machine.cpu().memory()[BUFFER as usize..][..2], [1, 2]);
+    assert!(machine.debugger().is_paused());
+}
+
+#[test]
+fn read_watchpoints_ignore_writes() {
+    let mut machine = machine();
+    machine
+        .debugger_mut()
+        .add_watchpoint(BUFFER..=BUFFER + 1, Access::Read);
+    assert_eq!(machine.run_frame().unwrap().stop, None);
+    assert_eq!(machine.cpu().pc(), DONE);
+}
+
+#[test]
+fn register_watches_report_the_change() {
+    let mut machine = machine();
+    machine.debugger_mut().watch_register(Register::V(3));
+    let stop = machine.run_frame().unwrap().stop;
+    assert_eq!(
+        stop,
+        Some(StopReason::RegisterChanged {
+            pc: SUB + 2,
+            register: Register::V(3),
+            old: 0,
+            new: 4,
+        })
+    );
+    assert_eq!(machine.cpu().pc(), SUB + 4);
+}
@@ -0,0 +1,132 @@
+//! Runs programs with and without the instruction cache and checks that the machine ends every
+//! frame in the same state either way.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use libchip8cpu::*;
+
+const FRAMES: u64 = 1200;
+
+fn machine(rom: &[u8], variant: Variant, instruction_cache: bool) -> Machine {
+    let mut cpu = CPU::new_with_variant(variant, variant.quirks()).with_seed(DEFAULT_SEED);
+    cpu.set_instruction_cache(instruction_cache);
+    cpu.load(rom).unwrap();
+    Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_SECOND)
+}
+
+/// Runs `rom` both ways for `FRAMES` frames, tapping a different key every half second so that
+/// games get past their title screens, and returns the final state.
+fn assert_identical(name: &str, rom: &[u8], variant: Variant) -> Vec<u8> {
+    let mut cached = machine(rom, variant, true);
+    let mut plain = machine(rom, variant, false);
+    for frame in 0..FRAMES {
+        if frame % 30 == 0 {
+            let key = (frame / 30 % 16) as u8;
+            let timestamp = Duration::from_secs(frame) / TIMER_FREQUENCY;
+            for machine in [&mut cached, &mut plain] {
+                machine
+                    .push_key_event(KeyEvent::new(key, true, timestamp))
+                    .unwrap();
+                machine
+                    .push_key_event(KeyEvent::new(key, false, timestamp))
+                    .unwrap();
+            }
+        }
+        let results = (cached.run_frame(), plain.run_frame());
+        assert_eq!(results.0, results.1, "{name}: frame {frame}");
+        assert!(
+            cached.cpu().save_state() == plain.cpu().save_state(),
+            "{name}: states differ after frame {frame}"
+        );
+        if results.0.is_err() {
+            break;
+        }
+    }
+    cached.cpu().save_state()
+}
+
+#[test]
+fn bundled_roms_run_the_same_with_the_cache() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_file() {
+            let name = path.file_name().unwrap().to_string_lossy();
+            assert_identical(&name, &fs::read(&path).unwrap(), Variant::Chip8);
+            count += 1;
+        }
+    }
+    assert!(count > 0);
+}
+
+fn register_after(source: &str, variant: Variant, register: usize) -> u8 {
+    let rom = asm::assemble(source).unwrap();
+    let mut machine = machine(&rom, variant, true);
+    assert_identical("self-modifying", &rom, variant);
+    for _ in 0..10 {
+        machine.run_frame().unwrap();
+    }
+    machine.cpu().registers()[register]
+}
+
+#[test]
+fn fx55_over_code_already_run() {
+    let source = "
+        LD V2, 0
+target: LD V3, 5
+        ADD V2, 1
+        SE V2, 2
+        JP patch
+done:   JP done
+patch:  LD V0, 0x63
+        LD V1, 0x2A
+        LD I, target
+        LD [I], V1
+        JP target
+";
+    assert_eq!(register_after(source, Variant::Chip8, 3), 0x2A);
+}
+
+#[test]
+fn fx33_over_code_already_run() {
+    let source = "
+        LD V2, 0
+target: LD V3, 5
+        LD V4, 7
+        ADD V2, 1
+        SE V2, 2
+        JP patch
+done:   JP done
+patch:  LD V0, 200
+        LD I, target + 1
+        LD B, V0
+        JP target
+";
+    // The hundreds digit lands in `LD V3` and the zeros make `LD V4` a `SYS 0`.
+    assert_eq!(register_after(source, Variant::Chip8, 3), 2);
+}
+
+#[test]
+fn rewritten_long_load_operand() {
+    let source = "
+        LD V2, 0
+load:   LD I, LONG first
+        LD V0, [I]
+        LD V6, V0
+        ADD V2, 1
+        SE V2, 2
+        JP patch
+done:   JP done
+patch:  LD V0, 0x02
+        LD V1, second - 0x200
+        LD I, load + 2
+        LD [I], V1
+        JP load
+first:  DB 0x11
+second: DB 0x22
+";
+    assert_eq!(register_after(source, Variant::XoChip, 6), 0x22);
+}
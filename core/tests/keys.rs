@@ -0,0 +1,98 @@
+//! How `FX0A` waits for keys under the press and release profiles.
+
+use libchip8cpu::*;
+
+const START: u16 = 0x200;
+
+/// A CPU about to run two `FX0A` in a row, into `V0` and then `V1`.
+fn cpu(quirks: Quirks) -> CPU {
+    let mut cpu = CPU::new_with_quirks(quirks);
+    let rom = asm::assemble("LD V0, K\nLD V1, K\ndone: JP done\n").unwrap();
+    cpu.load(&rom).unwrap();
+    cpu
+}
+
+fn ticks(cpu: &mut CPU, count: usize) {
+    for _ in 0..count {
+        cpu.tick().unwrap();
+    }
+}
+
+#[test]
+fn release_profile_waits_for_the_key_to_go_up() {
+    let mut cpu = cpu(Quirks::COSMAC_VIP);
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 10);
+    assert_eq!(cpu.pc(), START);
+    assert!(cpu.is_waiting_for_key());
+
+    cpu.keypress(5, false).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[0], 5);
+    assert_eq!(cpu.pc(), START + 2);
+    assert!(!cpu.is_waiting_for_key());
+}
+
+#[test]
+fn default_profile_completes_on_press() {
+    let mut cpu = cpu(Quirks::default());
+    ticks(&mut cpu, 3);
+    assert_eq!(cpu.pc(), START);
+
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[0], 5);
+    assert_eq!(cpu.pc(), START + 2);
+}
+
+#[test]
+fn default_profile_does_not_take_a_key_still_held() {
+    let mut cpu = cpu(Quirks::default());
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[0], 5);
+
+    ticks(&mut cpu, 10);
+    assert_eq!(cpu.pc(), START + 2);
+    assert!(cpu.is_waiting_for_key());
+
+    // Pressing it again after letting go counts.
+    cpu.keypress(5, false).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.pc(), START + 2);
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[1], 5);
+    assert_eq!(cpu.pc(), START + 4);
+}
+
+#[test]
+fn default_profile_takes_another_key_pressed_while_one_is_held() {
+    let mut cpu = cpu(Quirks::default());
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 2);
+    cpu.keypress(9, true).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[1], 9);
+    assert_eq!(cpu.pc(), START + 4);
+}
+
+#[test]
+fn release_profile_does_not_take_a_key_still_held() {
+    let mut cpu = cpu(Quirks::COSMAC_VIP);
+    cpu.keypress(5, true).unwrap();
+    ticks(&mut cpu, 1);
+    cpu.keypress(7, true).unwrap();
+    cpu.keypress(5, false).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[0], 5);
+
+    ticks(&mut cpu, 10);
+    assert_eq!(cpu.pc(), START + 2);
+    assert!(cpu.is_waiting_for_key());
+
+    cpu.keypress(7, false).unwrap();
+    ticks(&mut cpu, 1);
+    assert_eq!(cpu.registers()[1], 7);
+    assert_eq!(cpu.pc(), START + 4);
+}
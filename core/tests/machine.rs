@@ -0,0 +1,124 @@
+//! The machine's instruction rate, 60 Hz timer frames and the key events it hands out per
+//! frame.
+
+use std::time::Duration;
+
+use libchip8cpu::*;
+
+fn machine(instructions_per_second: u32) -> Machine {
+    let mut cpu = CPU::new();
+    cpu.load(&[0x12, 0x00]).unwrap();
+    Machine::new(cpu, instructions_per_second)
+}
+
+/// Frames counted after feeding `run_for` one second of wall-clock time in `step` slices.
+fn frames_in_one_second(instructions_per_second: u32, step: Duration) -> u64 {
+    let mut machine = machine(instructions_per_second);
+    let mut remaining = Duration::from_secs(1);
+    while !remaining.is_zero() {
+        let elapsed = step.min(remaining);
+        machine.run_for(elapsed).unwrap();
+        remaining -= elapsed;
+    }
+    machine.frame_count()
+}
+
+#[test]
+fn sixty_frames_per_second_however_time_is_sliced() {
+    for step in [1_000, 7_000, 16_700, 33_000, 250_000] {
+        let step = Duration::from_micros(step);
+        assert_eq!(
+            frames_in_one_second(DEFAULT_INSTRUCTIONS_PER_SECOND, step),
+            60,
+            "{step:?} steps"
+        );
+    }
+}
+
+#[test]
+fn rates_below_sixty_still_tick_at_sixty_hertz() {
+    for rate in [1, 30, 59] {
+        assert_eq!(
+            frames_in_one_second(rate, Duration::from_millis(16)),
+            60,
+            "{rate} instructions per second"
+        );
+        let mut machine = machine(rate);
+        machine.run_cycles(rate as u64).unwrap();
+        assert_eq!(machine.frame_count(), 60, "{rate} instructions");
+    }
+}
+
+#[test]
+fn run_cycles_counts_instructions() {
+    let mut machine = machine(DEFAULT_INSTRUCTIONS_PER_SECOND);
+    // 700 / 60 is 11.67 instructions a frame, so the first frame ends on the 12th.
+    machine.run_cycles(11).unwrap();
+    assert_eq!(machine.frame_count(), 0);
+    machine.run_cycles(1).unwrap();
+    assert_eq!(machine.frame_count(), 1);
+    machine.run_cycles(700 - 12).unwrap();
+    assert_eq!(machine.frame_count(), 60);
+}
+
+#[test]
+fn run_frame_advances_one_frame() {
+    for rate in [1, 59, 60, 61, DEFAULT_INSTRUCTIONS_PER_SECOND] {
+        let mut machine = machine(rate);
+        for frame in 1..=120 {
+            machine.run_frame().unwrap();
+            assert_eq!(
+                machine.frame_count(),
+                frame,
+                "{rate} instructions per second"
+            );
+        }
+    }
+}
+
+#[test]
+fn long_stalls_are_clamped() {
+    let mut machine = machine(DEFAULT_INSTRUCTIONS_PER_SECOND);
+    machine.run_for(Duration::from_secs(10)).unwrap();
+    // At most a quarter of a second is caught up.
+    assert_eq!(machine.frame_count(), 15);
+}
+
+#[test]
+fn timers_count_down_once_a_frame() {
+    let mut cpu = CPU::new();
+    // LD V0, 60; LD DT, V0; loop: JP loop
+    cpu.load(&[0x60, 0x3C, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+    let mut machine = Machine::new(cpu, 30);
+    // Each instruction spans two frames.
+    machine.run_cycles(2).unwrap();
+    assert_eq!(machine.frame_count(), 4);
+    assert_eq!(machine.cpu().delay_timer(), 58);
+    for _ in 0..28 {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.frame_count(), 32);
+    assert_eq!(machine.cpu().delay_timer(), 30);
+}
+
+#[test]
+fn key_events_in_one_frame_reach_the_cpu_in_order() {
+    let mut machine = machine(DEFAULT_INSTRUCTIONS_PER_SECOND);
+    for (key, pressed) in [(0, true), (5, true), (0, false)] {
+        machine
+            .push_key_event(KeyEvent::new(key, pressed, Duration::ZERO))
+            .unwrap();
+    }
+    machine.run_cycles(1).unwrap();
+    assert!(machine.cpu().is_key_pressed(0));
+    assert!(machine.cpu().is_key_pressed(5));
+
+    // Key 0 stays down for the rest of the frame and goes up with the next tick.
+    machine.run_cycles(10).unwrap();
+    assert_eq!(machine.frame_count(), 0);
+    assert!(machine.cpu().is_key_pressed(0));
+    machine.run_cycles(1).unwrap();
+    assert_eq!(machine.frame_count(), 1);
+    assert!(!machine.cpu().is_key_pressed(0));
+    assert!(machine.cpu().is_key_pressed(5));
+}
@@ -0,0 +1,78 @@
+//! Recording a movie, replaying it and catching a desync.
+
+use libchip8cpu::*;
+
+/// Counts the instructions run while key 5 is held and keeps drawing random numbers.
+const SOURCE: &str = "
+        LD V1, 5
+loop:   RND V3, 0xFF
+        SKNP V1
+        ADD V2, 1
+        JP loop
+";
+/// Frame and key of every change, pressed or released.
+const SCRIPT: [(u64, u8, bool); 6] = [
+    (50, 5, true),
+    (80, 5, false),
+    (150, 5, true),
+    (200, 5, false),
+    (250, 7, true),
+    (260, 7, false),
+];
+const FRAMES: u64 = 300;
+
+fn machine() -> Machine {
+    let mut cpu = CPU::new();
+    cpu.load(&asm::assemble(SOURCE).unwrap()).unwrap();
+    Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_SECOND)
+}
+
+fn record() -> Movie {
+    let mut machine = machine();
+    let mut recorder = MovieRecorder::new(&mut machine, 1234);
+    let mut script = SCRIPT.iter().peekable();
+    for frame in 0..FRAMES {
+        while let Some((_, key, pressed)) = script.next_if(|(at, _, _)| *at == frame) {
+            recorder.push_key(&mut machine, *key, *pressed).unwrap();
+        }
+        recorder.run_frame(&mut machine).unwrap();
+    }
+    recorder.finish()
+}
+
+fn play(movie: Movie) -> Result<(), CpuError> {
+    let mut machine = machine();
+    let mut player = MoviePlayer::new(movie, &mut machine)?;
+    while !player.is_finished(&machine) {
+        player.run_frame(&mut machine)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn recordings_replay_without_desync() {
+    let movie = record();
+    assert_eq!(movie.frames(), FRAMES);
+    let bytes = movie.to_bytes();
+    assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+    play(movie).unwrap();
+}
+
+#[test]
+fn tampered_input_desyncs_on_its_frame() {
+    let mut bytes = record().to_bytes();
+    // Events follow the 35 byte header as a one byte frame delta and a key byte each. The third
+    // presses key 5 on frame 150; make it key 6.
+    let key = 35 + 2 * 2 + 1;
+    assert_eq!(bytes[key], 0x85);
+    bytes[key] = 0x86;
+    let movie = Movie::from_bytes(&bytes).unwrap();
+    assert_eq!(play(movie), Err(CpuError::Desync { frame: 150 }));
+}
+
+#[test]
+fn another_seed_desyncs_on_the_first_frame() {
+    let mut movie = record();
+    movie.seed += 1;
+    assert_eq!(play(movie), Err(CpuError::Desync { frame: 0 }));
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libchip8cpu::{KeyMap, Quirks};
+use serde::Deserialize;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::settings::{parse_color, Settings};
+
+/// Settings that may each be left unset, as written in the config file or on the command line.
+/// Unset settings keep the value they had before `apply`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    pub speed: Option<u32>,
+    pub scale: Option<u32>,
+    /// Name of a quirks profile.
+    pub quirks: Option<String>,
+    /// `RRGGBB` color.
+    pub foreground: Option<String>,
+    /// `RRGGBB` color.
+    pub background: Option<String>,
+    pub fullscreen: Option<bool>,
+    pub mute: Option<bool>,
+    /// Buzzer volume from 0 to 100.
+    pub volume: Option<f32>,
+    pub waveform: Option<String>,
+    pub frequency: Option<f32>,
+    /// Name of a key map preset.
+    pub layout: Option<String>,
+    pub keymap: Option<PathBuf>,
+    pub start_paused: Option<bool>,
+    pub seed: Option<u64>,
+}
+
+impl Overrides {
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        if let Some(speed) = self.speed {
+            settings.instructions_per_second = positive(speed, "speed")?;
+        }
+        if let Some(scale) = self.scale {
+            settings.scale = positive(scale, "scale")?;
+        }
+        if let Some(name) = &self.quirks {
+            let quirks = Quirks::profile(name).ok_or_else(|| {
+                format!(
+                    "unknown quirks profile {name}, expected one of {}",
+                    Quirks::PROFILE_NAMES.join(", ")
+                )
+            })?;
+            settings.quirks = Some(quirks);
+        }
+        if let Some(color) = &self.foreground {
+            settings.foreground = parse_color(color)?;
+        }
+        if let Some(color) = &self.background {
+            settings.background = parse_color(color)?;
+        }
+        if let Some(volume) = self.volume {
+            if !(0.0..=100.0).contains(&volume) {
+                return Err(format!("invalid volume {volume}, expected 0 to 100"));
+            }
+            settings.tone.volume = volume / 100.0;
+        }
+        if let Some(waveform) = &self.waveform {
+            settings.tone.waveform = waveform.parse()?;
+        }
+        if let Some(frequency) = self.frequency {
+            settings.tone.frequency = positive(frequency, "frequency")?;
+        }
+        if let Some(name) = &self.layout {
+            settings.layout = KeyMap::preset(name).ok_or_else(|| {
+                format!(
+                    "unknown layout {name}, expected one of {}",
+                    KeyMap::PRESET_NAMES.join(", ")
+                )
+            })?;
+        }
+        if let Some(path) = &self.keymap {
+            settings.keymap = Some(path.clone());
+        }
+        settings.fullscreen = self.fullscreen.unwrap_or(settings.fullscreen);
+        settings.mute = self.mute.unwrap_or(settings.mute);
+        settings.start_paused = self.start_paused.unwrap_or(settings.start_paused);
+        settings.seed = self.seed.or(settings.seed);
+        Ok(())
+    }
+}
+
+fn positive<T: PartialOrd + Default + std::fmt::Display>(
+    value: T,
+    what: &str,
+) -> Result<T, String> {
+    if value > T::default() {
+        Ok(value)
+    } else {
+        Err(format!("invalid {what} {value}"))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Config {
+    #[serde(flatten)]
+    defaults: Overrides,
+    roms: BTreeMap<String, Overrides>,
+}
+
+/// The user's `config.toml`: top-level settings apply to every ROM and `[roms.<sha1>]` tables
+/// override them for the ROM with that SHA-1.
+///
+/// The file is kept as a document so that saving changes made at runtime preserves its comments
+/// and layout.
+pub struct ConfigFile {
+    path: PathBuf,
+    config: Config,
+    document: DocumentMut,
+    changed: bool,
+}
+
+impl ConfigFile {
+    /// `$XDG_CONFIG_HOME/emul8tor/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("emul8tor").join("config.toml"))
+    }
+    /// Reads the config at `path`. A missing file is an empty config.
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Unable to read {}: {err}", path.display())),
+        };
+        let error = |err: String| format!("{}: {err}", path.display());
+        let document: DocumentMut = source.parse().map_err(|err| error(format!("{err}")))?;
+        let config = toml_edit::de::from_str(&source).map_err(|err| error(format!("{err}")))?;
+        Ok(Self {
+            path,
+            config,
+            document,
+            changed: false,
+        })
+    }
+    /// The settings for the ROM with SHA-1 `rom_key`: the built-in defaults overridden by the
+    /// top-level settings and then by the ROM's table.
+    pub fn settings(&self, rom_key: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        let error = |err: String| format!("{}: {err}", self.path.display());
+        self.config.defaults.apply(&mut settings).map_err(error)?;
+        if let Some(overrides) = self.config.roms.get(rom_key) {
+            overrides.apply(&mut settings).map_err(error)?;
+        }
+        Ok(settings)
+    }
+    /// Sets `key` in the table of the ROM with SHA-1 `rom_key`, creating the table named after
+    /// `rom_name` if the ROM has none yet.
+    pub fn set_rom_value(&mut self, rom_key: &str, rom_name: &str, key: &str, value: Value) {
+        let roms = self.document["roms"].or_insert(Item::Table(implicit_table()));
+        let Some(roms) = roms.as_table_like_mut() else {
+            return;
+        };
+        let rom = roms.entry(rom_key).or_insert(Item::Table(Table::new()));
+        if let Some(rom) = rom.as_table_like_mut() {
+            if rom.get("name").is_none() {
+                rom.insert("name", Item::Value(rom_name.into()));
+            }
+            rom.insert(key, Item::Value(value));
+            self.changed = true;
+        }
+    }
+    /// Writes the file if anything was set since it was loaded.
+    pub fn save(&mut self) -> Result<(), String> {
+        if !self.changed {
+            return Ok(());
+        }
+        let error = |err: std::io::Error| format!("Unable to write {}: {err}", self.path.display());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        fs::write(&self.path, self.document.to_string()).map_err(error)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn implicit_table() -> Table {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    table
+}
+
+/// Hex SHA-1 of a ROM, the key of its table in the config file.
+pub fn rom_key(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
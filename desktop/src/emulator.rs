@@ -1,54 +1,356 @@
+use crate::config::ConfigFile;
 use crate::peripherals::*;
+use crate::settings::{Settings, PALETTES};
 use libchip8cpu::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const REWIND_BUDGET: usize = 8 * 1024 * 1024;
+/// Most frames a movie catches up by in one pass of the loop.
+const MAX_MOVIE_FRAMES: u32 = 15;
+const SPEED_STEP: u32 = 100;
+const VOLUME_STEP: f32 = 0.05;
+
+/// A movie being recorded or played back. Either drives the machine one whole frame at a time.
+enum MovieMode {
+    Recording(MovieRecorder, PathBuf),
+    Playing(MoviePlayer),
+}
+
+/// The config file runtime changes are saved to, under the running ROM's entry.
+struct RomConfig {
+    file: ConfigFile,
+    key: String,
+    name: String,
+}
 
-const TICK_PER_FRAME: usize = 30;
 pub struct Emulator {
-    cpu: CPU,
+    machine: Machine,
     peripherals: Peripheral,
+    quick_save: Option<Vec<u8>>,
+    quick_save_path: Option<PathBuf>,
+    rewind: RewindBuffer,
+    /// `Machine::frame_count` when the last rewind snapshot was taken.
+    rewind_frame: u64,
+    movie: Option<MovieMode>,
+    /// Wall-clock time not yet turned into movie frames.
+    movie_time: Duration,
+    foreground: [u8; 3],
+    background: [u8; 3],
+    config: Option<RomConfig>,
 }
 
 impl Emulator {
-    pub fn new() -> Self {
-        Self {
-            cpu: CPU::new(),
-            peripherals: Peripheral::new("Emulator", SCREEN_WIDTH, SCREEN_HEIGHT),
+    /// Loads `rom` as a `variant` program and opens the window and sound device to run it with
+    /// `settings`.
+    pub fn new(rom: &[u8], variant: Variant, settings: &Settings) -> Result<Self, String> {
+        let quirks = settings.quirks.unwrap_or_else(|| variant.quirks());
+        let mut cpu = CPU::new_with_variant(variant, quirks);
+        match settings.seed {
+            Some(seed) => cpu = cpu.with_seed(seed),
+            None => cpu.set_rng(Box::new(EntropyRng::new())),
+        }
+        cpu.load(rom).map_err(|err| err.to_string())?;
+        let mut machine = Machine::new(cpu, settings.instructions_per_second);
+        machine.set_tone(settings.tone);
+        machine
+            .debugger_mut()
+            .set_start_paused(settings.start_paused);
+        let peripherals = Peripheral::new("Emulator", SCREEN_WIDTH, SCREEN_HEIGHT, settings)?;
+        if let Some(sample_rate) = peripherals.sample_rate() {
+            machine
+                .set_sample_rate(sample_rate)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(Self {
+            machine,
+            peripherals,
+            quick_save: None,
+            quick_save_path: None,
+            rewind: RewindBuffer::new(REWIND_BUDGET, 1),
+            rewind_frame: 0,
+            movie: None,
+            movie_time: Duration::ZERO,
+            foreground: settings.foreground,
+            background: settings.background,
+            config: None,
+        })
+    }
+    /// Replaces the rewind history, e.g. to snapshot less often or with a different memory budget.
+    pub fn set_rewind_buffer(&mut self, rewind: RewindBuffer) {
+        self.rewind = rewind;
+    }
+    /// Breakpoints and watchpoints set here are honoured once `start` runs the ROM.
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        self.machine.debugger_mut()
+    }
+    /// Bindings of the keypad, which may be changed while running.
+    pub fn keymap_mut(&mut self) -> &mut KeyMap {
+        self.peripherals.keymap_mut()
+    }
+    /// Traces every executed instruction until `finish_trace` is called.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.machine.cpu_mut().set_tracer(tracer);
+    }
+    /// Detaches the tracer and flushes it, returning the result if one was attached.
+    pub fn finish_trace(&mut self) -> Option<std::io::Result<()>> {
+        self.machine.cpu_mut().take_tracer().map(Tracer::finish)
+    }
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.machine.set_tone(tone);
+    }
+    /// Saves speed, palette and volume changes made while running to `rom_key`'s entry in
+    /// `config`, once `save_config` is called.
+    pub fn set_config(&mut self, config: ConfigFile, rom_key: String, rom_name: String) {
+        self.config = Some(RomConfig {
+            file: config,
+            key: rom_key,
+            name: rom_name,
+        });
+    }
+    /// Writes the changes made while running to the config file, returning the result if one
+    /// was set.
+    pub fn save_config(&mut self) -> Option<Result<(), String>> {
+        self.config.as_mut().map(|config| config.file.save())
+    }
+    fn persist(&mut self, key: &str, value: impl Into<toml_edit::Value>) {
+        if let Some(config) = &mut self.config {
+            config
+                .file
+                .set_rom_value(&config.key, &config.name, key, value.into());
+        }
+    }
+    fn change_speed(&mut self, increase: bool) {
+        let speed = self.machine.instructions_per_second();
+        let speed = if increase {
+            speed.saturating_add(SPEED_STEP)
+        } else {
+            speed.saturating_sub(SPEED_STEP).max(SPEED_STEP)
+        };
+        self.machine.set_instructions_per_second(speed);
+        eprintln!("Speed: {speed} instructions per second");
+        self.persist("speed", speed as i64);
+    }
+    fn change_volume(&mut self, increase: bool) {
+        let mut tone = self.machine.tone();
+        let step = if increase { VOLUME_STEP } else { -VOLUME_STEP };
+        tone.volume = (tone.volume + step).clamp(0.0, 1.0);
+        self.machine.set_tone(tone);
+        let percent = (tone.volume * 100.0).round() as i64;
+        eprintln!("Volume: {percent}%");
+        self.persist("volume", percent);
+    }
+    /// Switches to the preset palette after the current one.
+    fn next_palette(&mut self) {
+        let current = PALETTES
+            .iter()
+            .position(|palette| *palette == (self.foreground, self.background));
+        let next = current.map_or(0, |idx| (idx + 1) % PALETTES.len());
+        (self.foreground, self.background) = PALETTES[next];
+        self.peripherals
+            .set_palette(self.foreground, self.background);
+        let hex = |[r, g, b]: [u8; 3]| format!("{r:02X}{g:02X}{b:02X}");
+        let (foreground, background) = (hex(self.foreground), hex(self.background));
+        self.persist("foreground", foreground);
+        self.persist("background", background);
+    }
+    /// Records the input from here on into a movie that `finish_movie` writes to `path`. The
+    /// machine restarts with `CXNN` seeded with `seed`; rewinding and quick loads are disabled
+    /// while recording, since they would break the replay.
+    pub fn record_movie(&mut self, path: PathBuf, seed: u64) {
+        let recorder = MovieRecorder::new(&mut self.machine, seed);
+        self.movie = Some(MovieMode::Recording(recorder, path));
+    }
+    /// Replays `movie` instead of taking keypad input, then hands control back to the player.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), CpuError> {
+        let player = MoviePlayer::new(movie, &mut self.machine)?;
+        self.movie = Some(MovieMode::Playing(player));
+        Ok(())
+    }
+    /// Writes the movie being recorded, returning the result if one was.
+    pub fn finish_movie(&mut self) -> Option<std::io::Result<()>> {
+        match self.movie.take() {
+            Some(MovieMode::Recording(recorder, path)) => {
+                Some(fs::write(path, recorder.finish().to_bytes()))
+            }
+            _ => None,
+        }
+    }
+    /// Runs as many whole frames as `elapsed` covers, with the movie's input.
+    fn run_movie(&mut self, elapsed: Duration) -> Result<Option<StopReason>, CpuError> {
+        let frame_time = Duration::from_secs(1) / TIMER_FREQUENCY;
+        self.movie_time = (self.movie_time + elapsed).min(frame_time * MAX_MOVIE_FRAMES);
+        let mut stop = None;
+        while self.movie_time >= frame_time && stop.is_none() {
+            self.movie_time -= frame_time;
+            let frame = match &mut self.movie {
+                Some(MovieMode::Recording(recorder, _)) => recorder.run_frame(&mut self.machine)?,
+                Some(MovieMode::Playing(player)) => {
+                    if player.is_finished(&self.machine) {
+                        eprintln!("Movie finished");
+                        self.movie = None;
+                        break;
+                    }
+                    player.run_frame(&mut self.machine)?
+                }
+                None => break,
+            };
+            stop = frame.stop;
+        }
+        Ok(stop)
+    }
+    /// Persists quick saves to `path` so they survive restarts, instead of keeping them in memory.
+    pub fn set_quick_save_path(&mut self, path: PathBuf) {
+        self.quick_save_path = Some(path);
+    }
+    fn quick_save(&mut self) {
+        let state = self.machine.cpu().save_state();
+        match &self.quick_save_path {
+            Some(path) => {
+                if let Err(err) = fs::write(path, &state) {
+                    eprintln!("Unable to write {}: {err}", path.display());
+                }
+            }
+            None => self.quick_save = Some(state),
+        }
+    }
+    fn quick_load(&mut self) {
+        let state = match &self.quick_save_path {
+            Some(path) => match fs::read(path) {
+                Ok(state) => state,
+                Err(err) => {
+                    eprintln!("Unable to read {}: {err}", path.display());
+                    return;
+                }
+            },
+            None => match &self.quick_save {
+                Some(state) => state.clone(),
+                None => return,
+            },
+        };
+        if let Err(err) = self.machine.cpu_mut().load_state(&state) {
+            eprintln!("Unable to load state: {err}");
+        }
+    }
+    fn report_pause(&self, reason: &str) {
+        match self
+            .machine
+            .debugger()
+            .current_instruction(self.machine.cpu())
+        {
+            Some(instruction) => eprintln!("Paused ({reason}), next: {instruction}"),
+            None => eprintln!("Paused ({reason})"),
         }
     }
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.cpu.load(rom);
+    fn handle_debug_hotkey(&mut self, hotkey: Hotkey) -> Result<(), CpuError> {
+        let reason = match hotkey {
+            Hotkey::TogglePause if self.machine.debugger().is_paused() => {
+                self.machine.debugger_mut().resume();
+                None
+            }
+            Hotkey::TogglePause => {
+                self.machine.debugger_mut().pause();
+                self.report_pause("user request");
+                None
+            }
+            Hotkey::StepInto => Some(self.machine.step_into()?),
+            Hotkey::StepOver => self.machine.step_over()?,
+            Hotkey::StepOut => {
+                self.machine.step_out();
+                None
+            }
+            Hotkey::StepFrame => {
+                self.machine.debugger_mut().step_frame();
+                None
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            self.report_pause(&reason.to_string());
+        }
+        Ok(())
     }
-    pub fn start(&mut self) {
+    /// Passes the audio emulated so far to the speaker. While the machine is not running, the
+    /// speaker is fed `elapsed` worth of silence so a playing tone fades out instead of cutting.
+    fn play_audio(&mut self, running: bool, elapsed: Duration) {
+        let count = if running {
+            self.machine.queued_audio_samples()
+        } else {
+            (elapsed.as_secs_f64() * self.machine.sample_rate() as f64) as usize
+        };
+        let mut samples = vec![0.0; count];
+        self.machine.fill_audio(&mut samples);
+        self.peripherals.queue_audio(&samples);
+    }
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), CpuError> {
+        self.machine.cpu_mut().load(rom)
+    }
+    pub fn start(&mut self) -> Result<(), CpuError> {
         let mut should_break = false;
-        let mut key_pressed_down: usize = 0;
-        let mut key_pressed_up: usize = 0;
-        let mut sound_timer_done = false;
+        let mut key_events = Vec::new();
+        let mut hotkey = None;
+        let mut rewinding = false;
+        let mut last_frame = Instant::now();
         loop {
-            self.peripherals.handle_event(
-                &mut should_break,
-                &mut key_pressed_down,
-                &mut key_pressed_up,
-            );
-            if key_pressed_down != 0 {
-                self.cpu.keypress(key_pressed_down, true);
-                key_pressed_down = 0;
-            }
-            if key_pressed_up != 0 {
-                self.cpu.keypress(key_pressed_up, false);
-                key_pressed_up = 0;
+            self.peripherals
+                .handle_event(&mut should_break, &mut key_events, &mut hotkey);
+            match hotkey.take() {
+                Some(
+                    Hotkey::QuickLoad | Hotkey::RewindStart | Hotkey::SpeedUp | Hotkey::SpeedDown,
+                ) if self.movie.is_some() => {
+                    eprintln!("Unavailable while a movie is recorded or played")
+                }
+                Some(Hotkey::QuickSave) => self.quick_save(),
+                Some(Hotkey::QuickLoad) => self.quick_load(),
+                Some(Hotkey::RewindStart) => rewinding = true,
+                Some(Hotkey::RewindStop) => rewinding = false,
+                Some(Hotkey::SpeedUp) => self.change_speed(true),
+                Some(Hotkey::SpeedDown) => self.change_speed(false),
+                Some(Hotkey::VolumeUp) => self.change_volume(true),
+                Some(Hotkey::VolumeDown) => self.change_volume(false),
+                Some(Hotkey::NextPalette) => self.next_palette(),
+                Some(hotkey) => self.handle_debug_hotkey(hotkey)?,
+                None => {}
+            }
+            for event in key_events.drain(..) {
+                match &mut self.movie {
+                    Some(MovieMode::Recording(recorder, _)) => {
+                        recorder.push_key(&mut self.machine, event.key, event.pressed)?
+                    }
+                    Some(MovieMode::Playing(_)) => {}
+                    None => self.machine.push_key_event(event)?,
+                }
             }
             if should_break {
                 break;
             }
-            for _ in 0..TICK_PER_FRAME {
-                self.cpu.tick();
-            }
-            self.peripherals.draw_screen(&self.cpu.get_display());
-
-            self.cpu.tick_timers(&mut sound_timer_done);
-            if sound_timer_done {
-                self.peripherals.beep();
-                sound_timer_done = false;
+            let now = Instant::now();
+            let elapsed = now - last_frame;
+            last_frame = now;
+            if rewinding {
+                self.rewind.rewind(self.machine.cpu_mut(), 1)?;
+                self.play_audio(false, elapsed);
+            } else if !self.machine.debugger().is_paused() {
+                let stop = if self.movie.is_some() {
+                    self.run_movie(elapsed)?
+                } else {
+                    self.machine.run_for(elapsed)?.stop
+                };
+                if let Some(reason) = stop {
+                    self.report_pause(&reason.to_string());
+                }
+                self.play_audio(true, elapsed);
+                if self.machine.frame_count() != self.rewind_frame {
+                    self.rewind_frame = self.machine.frame_count();
+                    self.rewind.record(self.machine.cpu());
+                }
+            } else {
+                self.play_audio(false, elapsed);
             }
+            self.peripherals
+                .draw_screen(self.machine.cpu().get_display());
         }
+        Ok(())
     }
 }
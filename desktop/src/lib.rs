@@ -1,2 +1,4 @@
+pub mod config;
 pub mod emulator;
 pub mod peripherals;
+pub mod settings;
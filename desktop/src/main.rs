@@ -1,22 +1,274 @@
-use std::fs::OpenOptions;
-use std::io::{prelude::*, BufReader};
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use desktop_emul8tor::config::{self, ConfigFile, Overrides};
+use desktop_emul8tor::emulator::Emulator;
+use desktop_emul8tor::settings::Settings;
+use libchip8cpu::{Access, Movie, TraceFormat, Tracer, Variant};
+
+const USAGE: &str = "usage: desktop_emul8tor <rom> [options]
+
+Options given here override those in the config file, by default
+$XDG_CONFIG_HOME/emul8tor/config.toml (or ~/.config/emul8tor/config.toml).
+
+  --config <file>           read settings from another config file
+
+Emulation:
+  --speed <ips>             instructions per second (default 700)
+  --quirks <profile>        default, cosmac-vip, chip-48, schip or xochip
+                            (default: the profile of the ROM's variant)
+  --seed <n>                seed CXNN so every run is the same
+  --start-paused            open paused in the debugger
+
+Display and input:
+  --scale <n>               window pixels per CHIP-8 pixel (default 10)
+  --fullscreen              fill the screen
+  --palette <fg>,<bg>       colors as RRGGBB (default FFFFFF,000000)
+  --layout <preset>         default, qwerty, azerty, dvorak or numpad (default: the
+                            1234/QWER/ASDF/ZXCV block by position, whatever the layout)
+  --keymap <file>           key bindings, one `<keypad key> = <key>` per line
+
+Sound:
+  --mute                    no sound
+  --volume <0-100>          buzzer volume (default 25)
+  --waveform <shape>        square, triangle or sine (default square)
+  --frequency <hz>          buzzer pitch (default 440)
+
+Debugging:
+  --break <addr>            pause before executing addr
+  --watch <addr>[-<addr>][:r|w|rw]
+                            pause when memory is read or written
+  --watch-reg <register>    pause when a register changes
+  --trace <file>            log every executed instruction
+  --trace-range <addr>-<addr>, --trace-last <count>, --trace-format text|compact
+                            limit what the trace logs and choose its format
+
+Movies:
+  --record <file>           record the session's input
+  --play <file>             replay a recorded session
+
+  -h, --help                show this help";
+
+/// Everything parsed from the command line.
+#[derive(Default)]
+struct Options {
+    rom_path: PathBuf,
+    config_path: Option<PathBuf>,
+    overrides: Overrides,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<(RangeInclusive<u16>, Access)>,
+    watched_registers: Vec<String>,
+    trace_path: Option<PathBuf>,
+    trace_range: Option<RangeInclusive<u16>>,
+    trace_last: Option<usize>,
+    trace_format: Option<TraceFormat>,
+    record_path: Option<PathBuf>,
+    play_path: Option<PathBuf>,
+}
 
 pub fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let rom_path = args.get(1).expect("Unable to find file");
-    let mut emulator: desktop_emul8tor::emulator::Emulator =
-        desktop_emul8tor::emulator::Emulator::new();
-    let rom = OpenOptions::new()
-        .read(true)
-        .open(Path::new(rom_path))
-        .expect("Unable to open file");
-    let mut f = BufReader::new(rom);
-    let mut rom_buffer = Vec::<u8>::new();
-    match f.read_to_end(&mut rom_buffer) {
-        Ok(_) => {}
-        Err(err) => eprintln!("{err}"),
-    }
-    emulator.load_rom(&rom_buffer);
-    emulator.start();
+    let options = parse_options(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\nRun with --help to see the available options.");
+        exit(2);
+    });
+    if let Err(err) = run(&options) {
+        eprintln!("{err}");
+        exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom_path = &options.rom_path;
+    let rom = fs::read(rom_path)
+        .map_err(|err| format!("Unable to read {}: {err}", rom_path.display()))?;
+    if rom.is_empty() {
+        return Err(format!("{} is empty", rom_path.display()));
+    }
+    let variant = match rom_path.extension().and_then(|ext| ext.to_str()) {
+        Some("sc8") => Variant::SuperChip,
+        Some("xo8") => Variant::XoChip,
+        _ => Variant::Chip8,
+    };
+    let rom_key = config::rom_key(&rom);
+    let config = match options
+        .config_path
+        .clone()
+        .or_else(ConfigFile::default_path)
+    {
+        Some(path) => Some(ConfigFile::load(path)?),
+        None => None,
+    };
+    let mut settings = match &config {
+        Some(config) => config.settings(&rom_key)?,
+        None => Settings::default(),
+    };
+    options.overrides.apply(&mut settings)?;
+    let mut emulator = Emulator::new(&rom, variant, &settings)
+        .map_err(|err| format!("Unable to run {}: {err}", rom_path.display()))?;
+    configure(&mut emulator, options, &settings)?;
+    emulator.set_quick_save_path(rom_path.with_extension("state"));
+    if let Some(config) = config {
+        let rom_name = rom_path.file_name().unwrap_or_default().to_string_lossy();
+        emulator.set_config(config, rom_key, rom_name.into_owned());
+    }
+
+    let result = emulator.start();
+    if let Some(Err(err)) = emulator.finish_trace() {
+        eprintln!("Unable to write trace: {err}");
+    }
+    if let Some(Err(err)) = emulator.finish_movie() {
+        eprintln!("Unable to write movie: {err}");
+    }
+    if let Some(Err(err)) = emulator.save_config() {
+        eprintln!("{err}");
+    }
+    result.map_err(|err| format!("Emulator halted: {err}"))
+}
+
+/// Applies the debugging, tracing and movie options once the ROM is loaded.
+fn configure(
+    emulator: &mut Emulator,
+    options: &Options,
+    settings: &Settings,
+) -> Result<(), String> {
+    let debugger = emulator.debugger_mut();
+    for addr in &options.breakpoints {
+        debugger.add_breakpoint(*addr);
+    }
+    for (range, access) in &options.watchpoints {
+        debugger.add_watchpoint(range.clone(), *access);
+    }
+    for register in &options.watched_registers {
+        debugger.watch_register(register.parse()?);
+    }
+    if let Some(path) = &options.trace_path {
+        let file = File::create(path)
+            .map_err(|err| format!("Unable to create {}: {err}", path.display()))?;
+        let format = options.trace_format.unwrap_or(TraceFormat::Text);
+        let mut tracer = Tracer::new(Box::new(BufWriter::new(file))).with_format(format);
+        if let Some(range) = &options.trace_range {
+            tracer = tracer.with_pc_range(range.clone());
+        }
+        if let Some(count) = options.trace_last {
+            tracer = tracer.with_ring_buffer(count);
+        }
+        emulator.set_tracer(tracer);
+    }
+    if let Some(path) = &options.play_path {
+        let error = |err: String| format!("Unable to play {}: {err}", path.display());
+        let data = fs::read(path).map_err(|err| error(err.to_string()))?;
+        let movie = Movie::from_bytes(&data).map_err(|err| error(err.to_string()))?;
+        emulator
+            .play_movie(movie)
+            .map_err(|err| error(err.to_string()))?;
+    }
+    if let Some(path) = &options.record_path {
+        let seed = settings.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64)
+        });
+        emulator.record_movie(path.clone(), seed);
+    }
+    Ok(())
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut rom_path = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} expects a value"));
+        let overrides = &mut options.overrides;
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                exit(0);
+            }
+            "--config" => options.config_path = Some(PathBuf::from(value()?)),
+            "--speed" => overrides.speed = Some(parse_number(&value()?, "speed")?),
+            "--scale" => overrides.scale = Some(parse_number(&value()?, "scale")?),
+            "--quirks" => overrides.quirks = Some(value()?),
+            "--palette" => {
+                let value = value()?;
+                let (fg, bg) = value
+                    .split_once(',')
+                    .ok_or_else(|| format!("invalid palette {value}, expected <fg>,<bg>"))?;
+                overrides.foreground = Some(fg.to_string());
+                overrides.background = Some(bg.to_string());
+            }
+            "--fullscreen" => overrides.fullscreen = Some(true),
+            "--mute" => overrides.mute = Some(true),
+            "--volume" => overrides.volume = Some(parse_number(&value()?, "volume")?),
+            "--waveform" => overrides.waveform = Some(value()?),
+            "--frequency" => overrides.frequency = Some(parse_number(&value()?, "frequency")?),
+            "--layout" => overrides.layout = Some(value()?),
+            "--keymap" => overrides.keymap = Some(PathBuf::from(value()?)),
+            "--start-paused" => overrides.start_paused = Some(true),
+            "--seed" => overrides.seed = Some(parse_number(&value()?, "seed")?),
+            "--break" => options.breakpoints.push(parse_address(&value()?)?),
+            "--watch" => {
+                let value = value()?;
+                let (range, access) = match value.split_once(':') {
+                    Some((range, "r")) => (range, Access::Read),
+                    Some((range, "w")) => (range, Access::Write),
+                    Some((range, "rw")) => (range, Access::ReadWrite),
+                    None => (value.as_str(), Access::ReadWrite),
+                    _ => return Err(format!("invalid watchpoint {value}")),
+                };
+                options.watchpoints.push((parse_range(range)?, access));
+            }
+            "--watch-reg" => options.watched_registers.push(value()?),
+            "--trace" => options.trace_path = Some(PathBuf::from(value()?)),
+            "--trace-range" => options.trace_range = Some(parse_range(&value()?)?),
+            "--trace-last" => {
+                let value = value()?;
+                let count = value
+                    .parse()
+                    .map_err(|_| format!("invalid instruction count {value}"))?;
+                options.trace_last = Some(count);
+            }
+            "--trace-format" => {
+                options.trace_format = Some(match value()?.as_str() {
+                    "text" => TraceFormat::Text,
+                    "compact" => TraceFormat::Compact,
+                    other => return Err(format!("unknown trace format {other}")),
+                })
+            }
+            "--record" => options.record_path = Some(PathBuf::from(value()?)),
+            "--play" => options.play_path = Some(PathBuf::from(value()?)),
+            _ if rom_path.is_none() && !arg.starts_with('-') => rom_path = Some(PathBuf::from(arg)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+    if options.record_path.is_some() && options.play_path.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+    // Report bad values now rather than after the config is read.
+    options.overrides.apply(&mut Settings::default())?;
+    options.rom_path = rom_path.ok_or("missing rom")?;
+    Ok(options)
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid {what} {value}"))
+}
+
+fn parse_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    match value.split_once('-') {
+        Some((start, end)) => Ok(parse_address(start)?..=parse_address(end)?),
+        None => Ok(parse_address(value)?..=parse_address(value)?),
+    }
+}
+
+fn parse_address(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address {value}"))
 }
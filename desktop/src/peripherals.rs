@@ -2,134 +2,222 @@ extern crate sdl2;
 
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 use sdl2::EventPump;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use libchip8cpu::{Display, HostKey, KeyEvent, KeyMap};
+
+use crate::settings::Settings;
+
+/// Plays the samples rendered by the core, with silence whenever it falls behind.
+struct SampleQueue {
+    samples: VecDeque<f32>,
+    capacity: usize,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for SampleQueue {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = self.samples.pop_front().unwrap_or(0.0);
         }
     }
 }
 
-const SCALE: u32 = 10;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    QuickSave,
+    QuickLoad,
+    RewindStart,
+    RewindStop,
+    TogglePause,
+    StepInto,
+    StepOver,
+    StepOut,
+    StepFrame,
+    SpeedUp,
+    SpeedDown,
+    VolumeUp,
+    VolumeDown,
+    NextPalette,
+}
+
+/// Colors of the XO-CHIP second plane and of both planes overlapping.
+const PLANE_COLORS: [Color; 2] = [Color::RGB(255, 102, 0), Color::RGB(102, 34, 0)];
 pub struct Peripheral {
     pump: EventPump,
     canvas: Canvas<Window>,
-    screen_width: u32,
-    speaker: Option<AudioDevice<SquareWave>>,
+    palette: [Color; 4],
+    keymap: KeyMap,
+    speaker: Option<AudioDevice<SampleQueue>>,
 }
 
-fn key_to_input(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
+/// Reads key bindings written one per line as `<keypad key> = <key>`, e.g. `5 = ArrowUp`. Keys
+/// are `KeyboardEvent::code` or SDL scancode names for a key by position, or a single character
+/// for the key typing it. Blank lines and lines starting with `#` are skipped.
+fn load_keymap(path: &Path) -> Result<KeyMap, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("Unable to read keymap {}: {err}", path.display()))?;
+    let mut keymap = KeyMap::empty();
+    for (idx, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("{}:{}: {message}", path.display(), idx + 1);
+        let (key, name) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected <key> = <key name>, found {line}")))?;
+        let key = u8::from_str_radix(key.trim(), 16)
+            .ok()
+            .filter(|key| *key < 16)
+            .ok_or_else(|| error(format!("invalid keypad key {}", key.trim())))?;
+        let name = name.trim();
+        let host = name
+            .parse()
+            .ok()
+            .or_else(|| Scancode::from_name(name).map(|code| HostKey::Scancode(code as u16)))
+            .ok_or_else(|| error(format!("unknown key name {name}")))?;
+        keymap.bind(host, key);
     }
+    Ok(keymap)
 }
 
-impl Peripheral {
-    pub fn new(title: &'static str, screen_width: u32, screen_height: u32) -> Self {
-        let context = match sdl2::init() {
-            Ok(context) => context,
-            Err(_) => panic!("Unable to create screen"),
-        };
-        let window = match context.video() {
-            Ok(video_subsystem) => match video_subsystem
-                .window(title, screen_width * SCALE, screen_height * SCALE)
-                .position_centered()
-                .opengl()
-                .build()
-            {
-                Ok(window) => window,
-                Err(_) => panic!("Unable to create screen"),
-            },
-            Err(_) => panic!("Unable to create screen"),
-        };
-        let canvas = match window.into_canvas().present_vsync().build() {
-            Ok(canvas) => canvas,
-            Err(_) => panic!("Unable to create screen"),
-        };
-        let pump = match context.event_pump() {
-            Ok(pump) => pump,
-            Err(_) => panic!("Unable to create screen"),
-        };
+/// The keypad key an SDL key presses. Keycodes of printable keys are the character they type.
+fn key_for(keymap: &KeyMap, scancode: Option<Scancode>, keycode: Option<Keycode>) -> Option<u8> {
+    let character = keycode
+        .and_then(|keycode| char::from_u32(keycode as i32 as u32))
+        .filter(|character| !character.is_control());
+    keymap.key_for(scancode.map(|code| code as u16), character)
+}
 
-        let speaker = match context.audio() {
-            Ok(audio_subsystem) => {
-                let desired_spec = AudioSpecDesired {
-                    freq: Some(44100),
-                    channels: Some(1), // mono
-                    samples: None,     // default sample size
-                };
-                match audio_subsystem.open_playback(None, &desired_spec, |spec| {
-                    // initialize the audio callback
-                    SquareWave {
-                        phase_inc: 440.0 / spec.freq as f32,
-                        phase: 0.0,
-                        volume: 0.25,
-                    }
-                }) {
-                    Ok(speaker) => Some(speaker),
-                    Err(err) => {
-                        eprintln!("unable to initialize sound {}", err);
-                        None
-                    }
+/// Opens the default playback device, or returns `None` and says why if there is no sound.
+fn open_speaker(context: &sdl2::Sdl) -> Option<AudioDevice<SampleQueue>> {
+    match context.audio() {
+        Ok(audio_subsystem) => {
+            let desired_spec = AudioSpecDesired {
+                freq: Some(44100),
+                channels: Some(1), // mono
+                samples: None,     // default sample size
+            };
+            match audio_subsystem.open_playback(None, &desired_spec, |spec| {
+                // initialize the audio callback, buffering at most a quarter second
+                SampleQueue {
+                    samples: VecDeque::new(),
+                    capacity: spec.freq as usize / 4,
+                }
+            }) {
+                Ok(speaker) => {
+                    speaker.resume();
+                    Some(speaker)
+                }
+                Err(err) => {
+                    eprintln!("unable to initialize sound {}", err);
+                    None
                 }
             }
-            Err(err) => {
-                eprintln!("unable to initialize sound {}", err);
-                None
-            }
+        }
+        Err(err) => {
+            eprintln!("unable to initialize sound {}", err);
+            None
+        }
+    }
+}
+
+impl Peripheral {
+    pub fn new(
+        title: &'static str,
+        screen_width: u32,
+        screen_height: u32,
+        settings: &Settings,
+    ) -> Result<Self, String> {
+        let keymap = match &settings.keymap {
+            Some(path) => load_keymap(path)?,
+            None => settings.layout.clone(),
         };
+        let screen_error = |err: String| format!("Unable to create screen: {err}");
+        let context = sdl2::init().map_err(screen_error)?;
+        let video_subsystem = context.video().map_err(screen_error)?;
+        let mut window = video_subsystem.window(
+            title,
+            screen_width * settings.scale,
+            screen_height * settings.scale,
+        );
+        window.position_centered().opengl();
+        if settings.fullscreen {
+            window.fullscreen_desktop();
+        }
+        let window = window
+            .build()
+            .map_err(|err| screen_error(err.to_string()))?;
+        let canvas = window
+            .into_canvas()
+            .present_vsync()
+            .build()
+            .map_err(|err| screen_error(err.to_string()))?;
+        let pump = context.event_pump().map_err(screen_error)?;
 
-        Self {
+        let speaker = if settings.mute {
+            None
+        } else {
+            open_speaker(&context)
+        };
+
+        let mut peripheral = Self {
             canvas,
             pump,
-            screen_width,
+            palette: [Color::BLACK, Color::WHITE, PLANE_COLORS[0], PLANE_COLORS[1]],
+            keymap,
             speaker,
-        }
+        };
+        peripheral.set_palette(settings.foreground, settings.background);
+        Ok(peripheral)
     }
     pub fn handle_event(
         &mut self,
         should_break: &mut bool,
-        key_pressed_down: &mut usize,
-        key_pressed_up: &mut usize,
+        key_events: &mut Vec<KeyEvent>,
+        hotkey: &mut Option<Hotkey>,
     ) {
         for evt in self.pump.poll_iter() {
+            // Keys bound to the keypad go to the keypad, even those that are also hotkeys.
+            let bound = match evt {
+                Event::KeyDown {
+                    keycode, scancode, ..
+                }
+                | Event::KeyUp {
+                    keycode, scancode, ..
+                } => key_for(&self.keymap, scancode, keycode),
+                _ => None,
+            };
+            if let Some(key) = bound {
+                match evt {
+                    Event::KeyDown {
+                        timestamp,
+                        repeat: false,
+                        ..
+                    } => {
+                        let timestamp = Duration::from_millis(timestamp as u64);
+                        key_events.push(KeyEvent::new(key, true, timestamp));
+                    }
+                    Event::KeyUp { timestamp, .. } => {
+                        let timestamp = Duration::from_millis(timestamp as u64);
+                        key_events.push(KeyEvent::new(key, false, timestamp));
+                    }
+                    _ => (),
+                }
+                continue;
+            }
             match evt {
                 Event::Quit { .. }
                 | Event::KeyDown {
@@ -137,35 +225,98 @@ impl Peripheral {
                     ..
                 } => *should_break = true,
                 Event::KeyDown {
-                    keycode: Some(key), ..
-                } => match key_to_input(key) {
-                    Some(key) => *key_pressed_down = key,
-                    None => {}
-                },
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => *hotkey = Some(Hotkey::QuickSave),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => *hotkey = Some(Hotkey::QuickLoad),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => *hotkey = Some(Hotkey::RewindStart),
                 Event::KeyUp {
-                    keycode: Some(key), ..
-                } => match key_to_input(key) {
-                    Some(key) => *key_pressed_up = key,
-                    None => {}
-                },
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => *hotkey = Some(Hotkey::RewindStop),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => *hotkey = Some(Hotkey::TogglePause),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => *hotkey = Some(Hotkey::StepFrame),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => *hotkey = Some(Hotkey::NextPalette),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => *hotkey = Some(Hotkey::VolumeDown),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => *hotkey = Some(Hotkey::VolumeUp),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => *hotkey = Some(Hotkey::SpeedUp),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => *hotkey = Some(Hotkey::SpeedDown),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => *hotkey = Some(Hotkey::StepOver),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    keymod,
+                    ..
+                } => {
+                    *hotkey = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        Some(Hotkey::StepOut)
+                    } else {
+                        Some(Hotkey::StepInto)
+                    }
+                }
                 _ => (),
             }
         }
     }
-    pub fn draw_screen(&mut self, screen_buffer: &[bool]) {
-        self.set_draw_color(Color::RGB(0, 0, 0));
+    /// Draws the display as large as fits the window, centered.
+    pub fn draw_screen(&mut self, display: Display) {
+        self.set_draw_color(self.palette[0]);
         self.clear();
-        self.set_draw_color(Color::RGB(255, 255, 255));
-        for (i, pixel) in screen_buffer.iter().enumerate() {
-            if *pixel {
-                let x: u32 = i as u32 % self.screen_width;
-                let y = i as u32 / self.screen_width;
-                let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+        let (width, height) = self.canvas.output_size().unwrap_or((0, 0));
+        let scale = (width / display.width).min(height / display.height).max(1);
+        let left = width.saturating_sub(display.width * scale) / 2;
+        let top = height.saturating_sub(display.height * scale) / 2;
+        for (i, pixel) in display.pixels.iter().enumerate() {
+            if *pixel != 0 {
+                self.set_draw_color(self.palette[*pixel as usize & 0b11]);
+                let x = left + i as u32 % display.width * scale;
+                let y = top + i as u32 / display.width * scale;
+                let rect = Rect::new(x as i32, y as i32, scale, scale);
                 self.canvas.fill_rect(rect).unwrap();
             }
         }
         self.present();
     }
+    /// Bindings of the keypad, which may be changed while running.
+    pub fn keymap_mut(&mut self) -> &mut KeyMap {
+        &mut self.keymap
+    }
+    /// Colors the display's first plane is drawn in and cleared to.
+    pub fn set_palette(&mut self, foreground: [u8; 3], background: [u8; 3]) {
+        let [r, g, b] = background;
+        self.palette[0] = Color::RGB(r, g, b);
+        let [r, g, b] = foreground;
+        self.palette[1] = Color::RGB(r, g, b);
+    }
     pub fn set_draw_color(&mut self, color: Color) {
         self.canvas.set_draw_color(color);
     }
@@ -178,16 +329,19 @@ impl Peripheral {
     pub fn present(&mut self) {
         self.canvas.present();
     }
-    pub fn beep(&self) {
-        if self.speaker.is_some() {
-            match &self.speaker {
-                Some(speaker) => {
-                    speaker.resume();
-                    std::thread::sleep(std::time::Duration::from_millis(50));
-                    speaker.pause();
-                }
-                None => {}
-            };
+    /// Sample rate of the audio device, if sound could be initialized.
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.speaker
+            .as_ref()
+            .map(|speaker| speaker.spec().freq as u32)
+    }
+    /// Hands samples to the audio device without blocking; they play after those already queued.
+    pub fn queue_audio(&mut self, samples: &[f32]) {
+        if let Some(speaker) = &mut self.speaker {
+            let mut queue = speaker.lock();
+            queue.samples.extend(samples);
+            let excess = queue.samples.len().saturating_sub(queue.capacity);
+            queue.samples.drain(..excess);
         }
     }
 }
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+
+use libchip8cpu::{KeyMap, Quirks, Tone, DEFAULT_INSTRUCTIONS_PER_SECOND};
+
+pub const DEFAULT_SCALE: u32 = 10;
+/// Foreground and background colors F2 cycles through.
+pub const PALETTES: [([u8; 3], [u8; 3]); 4] = [
+    ([0xFF, 0xFF, 0xFF], [0x00, 0x00, 0x00]),
+    ([0x33, 0xFF, 0x66], [0x10, 0x10, 0x10]),
+    ([0xFF, 0xB0, 0x00], [0x1A, 0x0F, 0x00]),
+    ([0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F]),
+];
+
+/// How a session runs and looks, as chosen in the config file and on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub instructions_per_second: u32,
+    /// Window pixels per low-resolution CHIP-8 pixel.
+    pub scale: u32,
+    /// Quirks to run with instead of those of the ROM's variant.
+    pub quirks: Option<Quirks>,
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub tone: Tone,
+    /// Key bindings used unless there is a keymap file.
+    pub layout: KeyMap,
+    /// File of key bindings replacing the layout.
+    pub keymap: Option<PathBuf>,
+    pub start_paused: bool,
+    /// Seed for `CXNN`. Without one every run differs.
+    pub seed: Option<u64>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            scale: DEFAULT_SCALE,
+            quirks: None,
+            foreground: PALETTES[0].0,
+            background: PALETTES[0].1,
+            fullscreen: false,
+            mute: false,
+            tone: Tone::default(),
+            layout: KeyMap::default(),
+            keymap: None,
+            start_paused: false,
+            seed: None,
+        }
+    }
+}
+
+/// Parses a color written as `RRGGBB`, optionally prefixed with `#`.
+pub fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let digits = value.trim().trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color {value}, expected RRGGBB"));
+    }
+    let rgb = u32::from_str_radix(digits, 16).map_err(|err| err.to_string())?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Ok([r, g, b])
+}
@@ -0,0 +1,111 @@
+; Probes the quirks of the interpreter running it and shows one row per quirk: its number and
+; 1 if the interpreter has it, 0 if not.
+;
+;   1  vf_reset          4  clipping
+;   2  memory_increment  5  shift
+;   3  display_wait      6  jump
+;
+; Assemble with `chip8-asm QUIRKS.s -o ../QUIRKS`.
+
+        LD V4, 1                ; quirk number
+        LD V6, 8                ; x of the current row
+        LD V7, 4                ; y of the current row
+
+; 8XY1 sets VF to 0.
+        LD VF, 5
+        LD V0, 0
+        OR V0, V0
+        LD V5, 0
+        SNE VF, 0
+        LD V5, 1
+        CALL show
+
+; FX65 moves I past the registers it loaded.
+        LD I, pair
+        LD V0, [I]
+        LD V0, [I]
+        LD V5, 0
+        SNE V0, 0xBB
+        LD V5, 1
+        CALL show
+
+; DXYN draws at most once a frame. Without the quirk, a loop drawing an empty sprite runs
+; about twice a frame at 700 instructions per second.
+        LD V0, 1
+        LD DT, V0
+sync:   LD V0, DT
+        SE V0, 0
+        JP sync
+        LD V0, 3
+        LD DT, V0
+        LD V1, 0
+        LD I, blank
+draws:  DRW V0, V0, 1
+        ADD V1, 1
+        LD V2, DT
+        SE V2, 0
+        JP draws
+        LD V0, 5
+        SUB V1, V0
+        LD V5, 1
+        SE VF, 0
+        LD V5, 0
+        CALL show
+
+; DXYN cuts sprites off at the right edge instead of wrapping them onto the left. The dot at
+; the left edge only collides with a wrapped sprite.
+        LD V0, 0
+        LD V1, 60
+        LD V2, 31
+        LD I, dot
+        DRW V0, V2, 1
+        LD I, line
+        DRW V1, V2, 1
+        LD V5, 1
+        SE VF, 0
+        LD V5, 0
+        DRW V1, V2, 1
+        LD I, dot
+        DRW V0, V2, 1
+        CALL show
+
+; 8XY6 shifts VX instead of VY.
+        LD V1, 4
+        LD V2, 1
+        SHR V1, V2
+        LD V5, 0
+        SNE V1, 2
+        LD V5, 1
+        CALL show
+
+; BNNN jumps to XNN + VX. `hop` is in 0x2XX, so that is V2.
+        LD V0, 0
+        LD V2, 2
+        JP V0, hop
+hop:    JP plain
+        LD V5, 1
+        JP jumped
+plain:  LD V5, 0
+jumped: CALL show
+
+done:   JP done
+
+; Draws quirk number V4 with result V5 in the row at V6, V7 and moves to the next row.
+show:   LD F, V4
+        DRW V6, V7, 5
+        LD V8, V6
+        ADD V8, 6
+        LD F, V5
+        DRW V8, V7, 5
+        ADD V4, 1
+        ADD V7, 8
+        SE V7, 28
+        RET
+        LD V7, 4
+        ADD V6, 32
+        RET
+
+pair:   DB 0xAA, 0xBB
+blank:  DB 0
+dot:    DB 0x80
+line:   DB 0xFF
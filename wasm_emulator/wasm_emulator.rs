@@ -1,19 +1,54 @@
-use js_sys::Uint8Array;
+use std::time::Duration;
+
+use js_sys::{Uint16Array, Uint8Array};
 use libchip8cpu::*;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, KeyboardEvent};
 
+const PALETTE: [&str; 4] = ["#994444", "#FFFFFF", "#FF6600", "#662200"];
+const REWIND_BUDGET: usize = 8 * 1024 * 1024;
+
 #[wasm_bindgen]
 pub struct WasmEmu {
-    chip8: CPU,
+    machine: Machine,
     ctx: CanvasRenderingContext2d,
+    rewind: RewindBuffer,
+    /// `Machine::frame_count` when the last rewind snapshot was taken.
+    rewind_frame: u64,
+    keymap: KeyMap,
 }
+
+/// The outcome of `WasmEmu::run_for`, mirroring `FrameResult`.
+#[wasm_bindgen]
+pub struct Frame {
+    pub display_dirty: bool,
+    pub sound_on: bool,
+    pub waiting_for_key: bool,
+    stop: Option<String>,
+}
+
+#[wasm_bindgen]
+impl Frame {
+    /// Why the debugger paused, if it did.
+    #[wasm_bindgen(getter)]
+    pub fn stop(&self) -> Option<String> {
+        self.stop.clone()
+    }
+}
+impl Default for WasmEmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl WasmEmu {
     #[wasm_bindgen(constructor)]
     pub fn new() -> WasmEmu {
-        let chip8 = CPU::new();
+        let mut cpu = CPU::new();
+        cpu.set_rng(Box::new(EntropyRng::new()));
+        let machine = Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_SECOND);
         let document = web_sys::window().unwrap().document().unwrap();
         let canvas = document.get_element_by_id("display_stage").unwrap();
         let canvas: HtmlCanvasElement = canvas
@@ -27,48 +62,367 @@ impl WasmEmu {
             .dyn_into::<CanvasRenderingContext2d>()
             .unwrap();
 
-        WasmEmu { chip8, ctx }
+        WasmEmu {
+            machine,
+            ctx,
+            rewind: RewindBuffer::new(REWIND_BUDGET, 1),
+            rewind_frame: 0,
+            keymap: KeyMap::default(),
+        }
     }
 
+    /// Advances the machine by `elapsed_ms` of wall-clock time and, once a timer frame has
+    /// passed, records it for `rewind`.
     #[wasm_bindgen]
-    pub fn tick(&mut self) {
-        self.chip8.tick();
+    pub fn run_for(&mut self, elapsed_ms: f64) -> Result<Frame, JsValue> {
+        let elapsed = Duration::from_secs_f64(elapsed_ms.max(0.0) / 1000.0);
+        let frame = self.machine.run_for(elapsed).map_err(to_js_error)?;
+        if self.machine.frame_count() != self.rewind_frame {
+            self.rewind_frame = self.machine.frame_count();
+            self.rewind.record(self.machine.cpu());
+        }
+        Ok(Frame {
+            display_dirty: frame.display_dirty,
+            sound_on: frame.sound_on,
+            waiting_for_key: frame.waiting_for_key,
+            stop: frame.stop.map(|reason| reason.to_string()),
+        })
     }
 
+    /// Starts rendering audio for an `AudioContext` running at `sample_rate`.
     #[wasm_bindgen]
-    pub fn tick_timers(&mut self) -> u8 {
-        let mut sound_timer_done = false;
-        self.chip8.tick_timers(&mut sound_timer_done);
-        if sound_timer_done {
-            1
-        } else {
-            0
-        }
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), JsValue> {
+        self.machine
+            .set_sample_rate(sample_rate)
+            .map_err(to_js_error)
+    }
+
+    /// Takes the audio emulated so far, for the AudioWorklet to play. At least `min_samples` are
+    /// returned, padded with silence, which keeps the worklet fed while the machine is paused.
+    #[wasm_bindgen]
+    pub fn audio_samples(&mut self, min_samples: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; self.machine.queued_audio_samples().max(min_samples)];
+        self.machine.fill_audio(&mut samples);
+        samples
+    }
+
+    #[wasm_bindgen]
+    pub fn set_waveform(&mut self, name: &str) -> Result<(), JsValue> {
+        let mut tone = self.machine.tone();
+        tone.waveform = name
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        self.machine.set_tone(tone);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn set_frequency(&mut self, frequency: f32) {
+        let mut tone = self.machine.tone();
+        tone.frequency = frequency;
+        self.machine.set_tone(tone);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_volume(&mut self, volume: f32) {
+        let mut tone = self.machine.tone();
+        tone.volume = volume.clamp(0.0, 1.0);
+        self.machine.set_tone(tone);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.machine
+            .set_instructions_per_second(instructions_per_second);
+    }
+
+    #[wasm_bindgen]
+    pub fn is_paused(&self) -> bool {
+        self.machine.debugger().is_paused()
+    }
+
+    #[wasm_bindgen]
+    pub fn pause(&mut self) {
+        self.machine.debugger_mut().pause();
+    }
+
+    #[wasm_bindgen]
+    pub fn resume(&mut self) {
+        self.machine.debugger_mut().resume();
+    }
+
+    #[wasm_bindgen]
+    pub fn step_into(&mut self) -> Result<String, JsValue> {
+        let reason = self.machine.step_into().map_err(to_js_error)?;
+        Ok(reason.to_string())
+    }
+
+    #[wasm_bindgen]
+    pub fn step_over(&mut self) -> Result<Option<String>, JsValue> {
+        let reason = self.machine.step_over().map_err(to_js_error)?;
+        Ok(reason.map(|reason| reason.to_string()))
+    }
+
+    #[wasm_bindgen]
+    pub fn step_out(&mut self) {
+        self.machine.step_out();
+    }
+
+    #[wasm_bindgen]
+    pub fn step_frame(&mut self) {
+        self.machine.debugger_mut().step_frame();
+    }
+
+    /// View of `V0`-`VF`, see `memory`.
+    #[wasm_bindgen]
+    pub fn registers(&self) -> Uint8Array {
+        // SAFETY: as in `memory`.
+        unsafe { Uint8Array::view(&self.machine.cpu().registers()[..]) }
+    }
+
+    #[wasm_bindgen]
+    pub fn i(&self) -> u16 {
+        self.machine.cpu().i()
+    }
+
+    #[wasm_bindgen]
+    pub fn pc(&self) -> u16 {
+        self.machine.cpu().pc()
+    }
+
+    #[wasm_bindgen]
+    pub fn sp(&self) -> u8 {
+        self.machine.cpu().sp()
+    }
+
+    /// View of the return addresses on the stack, outermost first, see `memory`.
+    #[wasm_bindgen]
+    pub fn stack(&self) -> Uint16Array {
+        // SAFETY: as in `memory`.
+        unsafe { Uint16Array::view(self.machine.cpu().stack()) }
+    }
+
+    #[wasm_bindgen]
+    pub fn delay_timer(&self) -> u8 {
+        self.machine.cpu().delay_timer()
+    }
+
+    #[wasm_bindgen]
+    pub fn sound_timer(&self) -> u8 {
+        self.machine.cpu().sound_timer()
+    }
+
+    /// View of the addressable memory, without copying it. The view follows the machine as it
+    /// runs but is only valid until the next call into the emulator: a call that allocates may
+    /// grow the wasm memory, which leaves the view pointing at a detached buffer.
+    #[wasm_bindgen]
+    pub fn memory(&self) -> Uint8Array {
+        // SAFETY: the view aliases the CPU's memory and JS must not keep it across a call that
+        // may reallocate, as documented above.
+        unsafe { Uint8Array::view(self.machine.cpu().memory()) }
+    }
+
+    #[wasm_bindgen]
+    pub fn poke(&mut self, addr: u16, value: u8) -> Result<(), JsValue> {
+        self.machine
+            .cpu_mut()
+            .poke(addr, value)
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_pc(&mut self, pc: u16) {
+        self.machine.cpu_mut().set_pc(pc);
+    }
+
+    /// Disassembly of the instruction at the program counter.
+    #[wasm_bindgen]
+    pub fn current_instruction(&self) -> Option<String> {
+        self.machine
+            .debugger()
+            .current_instruction(self.machine.cpu())
+            .map(|instruction| instruction.to_string())
+    }
+
+    #[wasm_bindgen]
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.machine.debugger_mut().add_breakpoint(pc);
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.machine.debugger_mut().remove_breakpoint(pc)
+    }
+
+    /// `access` is one of `"r"`, `"w"` or `"rw"`.
+    #[wasm_bindgen]
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, access: &str) -> Result<(), JsValue> {
+        let access = match access {
+            "r" => Access::Read,
+            "w" => Access::Write,
+            "rw" => Access::ReadWrite,
+            _ => return Err(JsValue::from_str(&format!("unknown access {access}"))),
+        };
+        self.machine
+            .debugger_mut()
+            .add_watchpoint(start..=end, access);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16) -> bool {
+        self.machine
+            .debugger_mut()
+            .remove_watchpoint(&(start..=end))
+    }
+
+    #[wasm_bindgen]
+    pub fn watch_register(&mut self, name: &str) -> Result<(), JsValue> {
+        let register = name
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        self.machine.debugger_mut().watch_register(register);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn unwatch_register(&mut self, name: &str) -> Result<bool, JsValue> {
+        let register = name
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        Ok(self.machine.debugger_mut().unwatch_register(register))
+    }
+
+    #[wasm_bindgen]
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, JsValue> {
+        self.rewind
+            .rewind(self.machine.cpu_mut(), frames)
+            .map_err(to_js_error)
     }
 
     #[wasm_bindgen]
     pub fn reset(&mut self) {
-        self.chip8.reset();
+        self.machine.reset();
+        self.rewind.clear();
     }
 
     #[wasm_bindgen]
-    pub fn keypress(&mut self, key_evt: KeyboardEvent, pressed: bool) {
-        let key = key_evt.key();
-        if let Some(key_index) = key_to_input(&key) {
-            self.chip8.keypress(key_index, pressed)
+    pub fn set_variant(&mut self, name: &str) -> Result<(), JsValue> {
+        let variant = match name {
+            "chip8" => Variant::Chip8,
+            "schip" => Variant::SuperChip,
+            "xochip" => Variant::XoChip,
+            _ => return Err(JsValue::from_str(&format!("unknown variant {name}"))),
+        };
+        self.machine.cpu_mut().set_variant(variant);
+        self.machine.cpu_mut().set_quirks(variant.quirks());
+        Ok(())
+    }
+
+    /// Makes `CXNN` reproducible from here on, e.g. to compare runs between builds.
+    #[wasm_bindgen]
+    pub fn set_seed(&mut self, seed: u32) {
+        self.machine
+            .cpu_mut()
+            .set_rng(Box::new(XorShiftRng::new(seed as u64)));
+    }
+
+    #[wasm_bindgen]
+    pub fn audio_pattern(&self) -> Vec<u8> {
+        self.machine.cpu().audio_pattern().to_vec()
+    }
+
+    #[wasm_bindgen]
+    pub fn audio_playback_rate(&self) -> f32 {
+        self.machine.cpu().audio_playback_rate()
+    }
+
+    /// Passes a key change to the machine, returning the keypad key the host key is bound to.
+    #[wasm_bindgen]
+    pub fn keypress(
+        &mut self,
+        key_evt: KeyboardEvent,
+        pressed: bool,
+    ) -> Result<Option<u8>, JsValue> {
+        let scancode = match HostKey::from_code(&key_evt.code()) {
+            Some(HostKey::Scancode(scancode)) => Some(scancode),
+            _ => None,
+        };
+        let name = key_evt.key();
+        let mut chars = name.chars();
+        let character = chars.next().filter(|_| chars.next().is_none());
+        let key = self.keymap.key_for(scancode, character);
+        if let (Some(key), false) = (key, key_evt.repeat()) {
+            let timestamp = Duration::from_secs_f64(key_evt.time_stamp().max(0.0) / 1000.0);
+            self.machine
+                .push_key_event(KeyEvent::new(key, pressed, timestamp))
+                .map_err(to_js_error)?;
         }
+        Ok(key)
     }
+
+    /// Replaces the key bindings with a preset: default, qwerty, azerty, dvorak or numpad.
     #[wasm_bindgen]
-    pub fn load_rom(&mut self, data: Uint8Array) {
-        self.chip8.load(&data.to_vec());
+    pub fn set_key_layout(&mut self, name: &str) -> Result<(), JsValue> {
+        self.keymap = KeyMap::preset(name)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown layout {name}")))?;
+        Ok(())
+    }
+
+    /// Makes `host`, a `KeyboardEvent::code` name or a single character, press keypad `key`.
+    #[wasm_bindgen]
+    pub fn bind_key(&mut self, host: &str, key: u8) -> Result<(), JsValue> {
+        let host = host
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        self.keymap.bind(host, key);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn unbind_key(&mut self, host: &str) -> Result<(), JsValue> {
+        let host = host
+            .parse()
+            .map_err(|err: String| JsValue::from_str(&err))?;
+        self.keymap.unbind(host);
+        Ok(())
+    }
+
+    /// The host keys bound to keypad `key`, as `KeyboardEvent::code` names or characters.
+    #[wasm_bindgen]
+    pub fn keys_for(&self, key: u8) -> Vec<String> {
+        self.keymap
+            .keys_for(key)
+            .map(|host| host.to_string())
+            .collect()
+    }
+    #[wasm_bindgen]
+    pub fn load_rom(&mut self, data: Uint8Array) -> Result<(), JsValue> {
+        self.machine
+            .cpu_mut()
+            .load(&data.to_vec())
+            .map_err(to_js_error)
+    }
+    #[wasm_bindgen]
+    pub fn save_state(&self) -> Uint8Array {
+        Uint8Array::from(&self.machine.cpu().save_state()[..])
+    }
+    #[wasm_bindgen]
+    pub fn load_state(&mut self, data: Uint8Array) -> Result<(), JsValue> {
+        self.machine
+            .cpu_mut()
+            .load_state(&data.to_vec())
+            .map_err(to_js_error)
     }
     #[wasm_bindgen]
     pub fn draw_screen(&mut self, scale: usize) {
-        let display = self.chip8.get_display();
-        for i in 0..((SCREEN_WIDTH * SCREEN_HEIGHT) as usize) {
-            if display[i] {
-                let x = i % SCREEN_WIDTH as usize;
-                let y = i / SCREEN_WIDTH as usize;
+        let display = self.machine.cpu().get_display();
+        let scale = scale * SCREEN_WIDTH as usize / display.width as usize;
+        for (i, pixel) in display.pixels.iter().enumerate() {
+            if *pixel != 0 {
+                self.ctx.set_fill_style_str(PALETTE[*pixel as usize & 0b11]);
+                let x = i % display.width as usize;
+                let y = i / display.width as usize;
                 self.ctx.fill_rect(
                     (x * scale) as f64,
                     (y * scale) as f64,
@@ -80,24 +434,6 @@ impl WasmEmu {
     }
 }
 
-fn key_to_input(key: &str) -> Option<usize> {
-    match key {
-        "1" => Some(0x1),
-        "2" => Some(0x2),
-        "3" => Some(0x3),
-        "4" => Some(0xC),
-        "q" => Some(0x4),
-        "w" => Some(0x5),
-        "e" => Some(0x6),
-        "r" => Some(0xD),
-        "a" => Some(0x7),
-        "s" => Some(0x8),
-        "d" => Some(0x9),
-        "f" => Some(0xE),
-        "z" => Some(0xA),
-        "x" => Some(0x0),
-        "c" => Some(0xB),
-        "v" => Some(0xF),
-        _ => None,
-    }
+fn to_js_error(err: CpuError) -> JsValue {
+    JsValue::from_str(&err.to_string())
 }
@@ -0,0 +1,39 @@
+// Plays the samples rendered by the emulator core. The main thread posts a Float32Array after
+// every frame; the processor outputs them in order and silence whenever it runs dry.
+class SampleQueueProcessor extends AudioWorkletProcessor {
+  constructor() {
+    super();
+    this.chunks = [];
+    this.offset = 0;
+    this.queued = 0;
+    this.port.onmessage = (e) => {
+      this.chunks.push(e.data);
+      this.queued += e.data.length;
+      // Keep at most a quarter second buffered so latency cannot build up.
+      while (this.queued - this.offset > sampleRate / 4 && this.chunks.length > 1) {
+        this.queued -= this.chunks.shift().length;
+        this.offset = 0;
+      }
+    };
+  }
+  process(inputs, outputs) {
+    const out = outputs[0][0];
+    let written = 0;
+    while (written < out.length && this.chunks.length) {
+      const chunk = this.chunks[0];
+      const count = Math.min(out.length - written, chunk.length - this.offset);
+      out.set(chunk.subarray(this.offset, this.offset + count), written);
+      written += count;
+      this.offset += count;
+      if (this.offset === chunk.length) {
+        this.chunks.shift();
+        this.queued -= chunk.length;
+        this.offset = 0;
+      }
+    }
+    out.fill(0, written);
+    return true;
+  }
+}
+
+registerProcessor('chip8-audio', SampleQueueProcessor);
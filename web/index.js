@@ -3,15 +3,17 @@ import init, * as wasm from './pkg/libemulator.js'
 const WIDTH = 64;
 const HEIGHT = 32;
 const SCALE = 10;
-const TICKS_PER_FRAME = 10;
 const SCALED_HEIGHT = HEIGHT * SCALE;
 const SCALED_WIDTH = WIDTH * SCALE;
-const snd = new Audio("data:audio/wav;base64,//uQRAAAAWMSLwUIYAAsYkXgoQwAEaYLWfkWgAI0wWs/ItAAAGDgYtAgAyN+QWaAAihwMWm4G8QQRDiMcCBcH3Cc+CDv/7xA4Tvh9Rz/y8QADBwMWgQAZG/ILNAARQ4GLTcDeIIIhxGOBAuD7hOfBB3/94gcJ3w+o5/5eIAIAAAVwWgQAVQ2ORaIQwEMAJiDg95G4nQL7mQVWI6GwRcfsZAcsKkJvxgxEjzFUgfHoSQ9Qq7KNwqHwuB13MA4a1q/DmBrHgPcmjiGoh//EwC5nGPEmS4RcfkVKOhJf+WOgoxJclFz3kgn//dBA+ya1GhurNn8zb//9NNutNuhz31f////9vt///z+IdAEAAAK4LQIAKobHItEIYCGAExBwe8jcToF9zIKrEdDYIuP2MgOWFSE34wYiR5iqQPj0JIeoVdlG4VD4XA67mAcNa1fhzA1jwHuTRxDUQ//iYBczjHiTJcIuPyKlHQkv/LHQUYkuSi57yQT//uggfZNajQ3Vmz+Zt//+mm3Wm3Q576v////+32///5/EOgAAADVghQAAAAA//uQZAUAB1WI0PZugAAAAAoQwAAAEk3nRd2qAAAAACiDgAAAAAAABCqEEQRLCgwpBGMlJkIz8jKhGvj4k6jzRnqasNKIeoh5gI7BJaC1A1AoNBjJgbyApVS4IDlZgDU5WUAxEKDNmmALHzZp0Fkz1FMTmGFl1FMEyodIavcCAUHDWrKAIA4aa2oCgILEBupZgHvAhEBcZ6joQBxS76AgccrFlczBvKLC0QI2cBoCFvfTDAo7eoOQInqDPBtvrDEZBNYN5xwNwxQRfw8ZQ5wQVLvO8OYU+mHvFLlDh05Mdg7BT6YrRPpCBznMB2r//xKJjyyOh+cImr2/4doscwD6neZjuZR4AgAABYAAAABy1xcdQtxYBYYZdifkUDgzzXaXn98Z0oi9ILU5mBjFANmRwlVJ3/6jYDAmxaiDG3/6xjQQCCKkRb/6kg/wW+kSJ5//rLobkLSiKmqP/0ikJuDaSaSf/6JiLYLEYnW/+kXg1WRVJL/9EmQ1YZIsv/6Qzwy5qk7/+tEU0nkls3/zIUMPKNX/6yZLf+kFgAfgGyLFAUwY//uQZAUABcd5UiNPVXAAAApAAAAAE0VZQKw9ISAAACgAAAAAVQIygIElVrFkBS+Jhi+EAuu+lKAkYUEIsmEAEoMeDmCETMvfSHTGkF5RWH7kz/ESHWPAq/kcCRhqBtMdokPdM7vil7RG98A2sc7zO6ZvTdM7pmOUAZTnJW+NXxqmd41dqJ6mLTXxrPpnV8avaIf5SvL7pndPvPpndJR9Kuu8fePvuiuhorgWjp7Mf/PRjxcFCPDkW31srioCExivv9lcwKEaHsf/7ow2Fl1T/9RkXgEhYElAoCLFtMArxwivDJJ+bR1HTKJdlEoTELCIqgEwVGSQ+hIm0NbK8WXcTEI0UPoa2NbG4y2K00JEWbZavJXkYaqo9CRHS55FcZTjKEk3NKoCYUnSQ0rWxrZbFKbKIhOKPZe1cJKzZSaQrIyULHDZmV5K4xySsDRKWOruanGtjLJXFEmwaIbDLX0hIPBUQPVFVkQkDoUNfSoDgQGKPekoxeGzA4DUvnn4bxzcZrtJyipKfPNy5w+9lnXwgqsiyHNeSVpemw4bWb9psYeq//uQZBoABQt4yMVxYAIAAAkQoAAAHvYpL5m6AAgAACXDAAAAD59jblTirQe9upFsmZbpMudy7Lz1X1DYsxOOSWpfPqNX2WqktK0DMvuGwlbNj44TleLPQ+Gsfb+GOWOKJoIrWb3cIMeeON6lz2umTqMXV8Mj30yWPpjoSa9ujK8SyeJP5y5mOW1D6hvLepeveEAEDo0mgCRClOEgANv3B9a6fikgUSu/DmAMATrGx7nng5p5iimPNZsfQLYB2sDLIkzRKZOHGAaUyDcpFBSLG9MCQALgAIgQs2YunOszLSAyQYPVC2YdGGeHD2dTdJk1pAHGAWDjnkcLKFymS3RQZTInzySoBwMG0QueC3gMsCEYxUqlrcxK6k1LQQcsmyYeQPdC2YfuGPASCBkcVMQQqpVJshui1tkXQJQV0OXGAZMXSOEEBRirXbVRQW7ugq7IM7rPWSZyDlM3IuNEkxzCOJ0ny2ThNkyRai1b6ev//3dzNGzNb//4uAvHT5sURcZCFcuKLhOFs8mLAAEAt4UWAAIABAAAAAB4qbHo0tIjVkUU//uQZAwABfSFz3ZqQAAAAAngwAAAE1HjMp2qAAAAACZDgAAAD5UkTE1UgZEUExqYynN1qZvqIOREEFmBcJQkwdxiFtw0qEOkGYfRDifBui9MQg4QAHAqWtAWHoCxu1Yf4VfWLPIM2mHDFsbQEVGwyqQoQcwnfHeIkNt9YnkiaS1oizycqJrx4KOQjahZxWbcZgztj2c49nKmkId44S71j0c8eV9yDK6uPRzx5X18eDvjvQ6yKo9ZSS6l//8elePK/Lf//IInrOF/FvDoADYAGBMGb7FtErm5MXMlmPAJQVgWta7Zx2go+8xJ0UiCb8LHHdftWyLJE0QIAIsI+UbXu67dZMjmgDGCGl1H+vpF4NSDckSIkk7Vd+sxEhBQMRU8j/12UIRhzSaUdQ+rQU5kGeFxm+hb1oh6pWWmv3uvmReDl0UnvtapVaIzo1jZbf/pD6ElLqSX+rUmOQNpJFa/r+sa4e/pBlAABoAAAAA3CUgShLdGIxsY7AUABPRrgCABdDuQ5GC7DqPQCgbbJUAoRSUj+NIEig0YfyWUho1VBBBA//uQZB4ABZx5zfMakeAAAAmwAAAAF5F3P0w9GtAAACfAAAAAwLhMDmAYWMgVEG1U0FIGCBgXBXAtfMH10000EEEEEECUBYln03TTTdNBDZopopYvrTTdNa325mImNg3TTPV9q3pmY0xoO6bv3r00y+IDGid/9aaaZTGMuj9mpu9Mpio1dXrr5HERTZSmqU36A3CumzN/9Robv/Xx4v9ijkSRSNLQhAWumap82WRSBUqXStV/YcS+XVLnSS+WLDroqArFkMEsAS+eWmrUzrO0oEmE40RlMZ5+ODIkAyKAGUwZ3mVKmcamcJnMW26MRPgUw6j+LkhyHGVGYjSUUKNpuJUQoOIAyDvEyG8S5yfK6dhZc0Tx1KI/gviKL6qvvFs1+bWtaz58uUNnryq6kt5RzOCkPWlVqVX2a/EEBUdU1KrXLf40GoiiFXK///qpoiDXrOgqDR38JB0bw7SoL+ZB9o1RCkQjQ2CBYZKd/+VJxZRRZlqSkKiws0WFxUyCwsKiMy7hUVFhIaCrNQsKkTIsLivwKKigsj8XYlwt/WKi2N4d//uQRCSAAjURNIHpMZBGYiaQPSYyAAABLAAAAAAAACWAAAAApUF/Mg+0aohSIRobBAsMlO//Kk4soosy1JSFRYWaLC4qZBYWFRGZdwqKiwkNBVmoWFSJkWFxX4FFRQWR+LsS4W/rFRb/////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////////VEFHAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAU291bmRib3kuZGUAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAMjAwNGh0dHA6Ly93d3cuc291bmRib3kuZGUAAAAAAAAAACU=");
 
 let anim_frame = 0;
+let lastFrameTime = null;
+let audio = null;
 
 const canvas = document.getElementById('display_stage');
 const romName = document.getElementById('rom_file');
+const debugStatus = document.getElementById('debug-status');
+const registerPanel = document.getElementById('registers');
 
 const ctx = canvas.getContext('2d');
 canvas.height = SCALED_HEIGHT;
@@ -22,7 +24,8 @@ ctx.fillRect(0, 0, SCALED_WIDTH, SCALED_HEIGHT);
 let gameState =
 {
   romFile: null,
-  isPaused: false
+  isPaused: false,
+  isRewinding: false
 }
 
 const readAndLoadFile = (file, emulator) => {
@@ -36,35 +39,52 @@ const readAndLoadFile = (file, emulator) => {
     let buffer = fileReader.result;
     const rom = new Uint8Array(buffer);
     emulator.reset();
-    emulator.load_rom(rom);
-    mainLoop(emulator);
+    emulator.set_variant(variantFor(file.name));
+    try {
+      emulator.load_rom(rom);
+    } catch (error) {
+      halt(error);
+      return;
+    }
+    lastFrameTime = null;
+    mainLoop(emulator, performance.now());
   }
   fileReader.readAsArrayBuffer(file);
 }
+const highlightKey = (key, pressed) => {
+  if (key === undefined) {
+    return;
+  }
+  document.getElementById(`key-${key.toString(16).toUpperCase()}`).classList.toggle('pressed', pressed);
+}
 const run = async () => {
   await init();
   const pauseButton = document.getElementById('pause');
   const resetButton = document.getElementById('reset');
+  const saveButton = document.getElementById('save');
+  const loadButton = document.getElementById('load');
+  const layoutSelect = document.getElementById('layout');
   let emulator = new wasm.WasmEmu();
   let input = document.getElementById("rom_select");
   document.addEventListener('keydown', e => {
-    emulator.keypress(e, true)
-    try {
-      let key = document.querySelector(`.key#key-${e.key.toUpperCase()}`);
-      key.classList.add(['pressed']);
-    } catch (error) {
-
+    startAudio(emulator);
+    if (e.key === 'Backspace') {
+      gameState = { ...gameState, isRewinding: true }
+      return;
     }
+    highlightKey(emulator.keypress(e, true), true);
   });
   document.addEventListener('keyup', e => {
-    try {
-      let key = document.querySelector(`.key.pressed`)
-      key.classList.remove(['pressed']);
-    } catch (error) {
-
+    if (e.key === 'Backspace') {
+      gameState = { ...gameState, isRewinding: false }
+      return;
     }
-    emulator.keypress(e, false)
+    highlightKey(emulator.keypress(e, false), false);
   });
+  layoutSelect.addEventListener('change', () => {
+    emulator.set_key_layout(layoutSelect.value);
+    layoutSelect.blur();
+  })
   pauseButton.addEventListener('click', () => {
     gameState = { ...gameState, isPaused: !gameState.isPaused }
   })
@@ -76,27 +96,137 @@ const run = async () => {
       readAndLoadFile(gameState.romFile, emulator);
     }
   })
+  saveButton.addEventListener('click', () => {
+    if (gameState.romFile) {
+      const state = emulator.save_state();
+      localStorage.setItem(stateKey(gameState.romFile), Array.from(state, b => String.fromCharCode(b)).join(''));
+    }
+  })
+  loadButton.addEventListener('click', () => {
+    if (!gameState.romFile) return;
+    const saved = localStorage.getItem(stateKey(gameState.romFile));
+    if (saved === null) return;
+    try {
+      emulator.load_state(Uint8Array.from(saved, c => c.charCodeAt(0)));
+    } catch (error) {
+      console.error(error);
+    }
+  })
+  const showPause = (reason) => showDebugStatus(emulator, reason);
+  const debugCommand = (command) => {
+    try {
+      command();
+    } catch (error) {
+      debugStatus.innerText = `${error}`;
+      console.error(error);
+    }
+  }
+  document.getElementById('continue').addEventListener('click', () => {
+    if (emulator.is_paused()) {
+      emulator.resume();
+      debugStatus.innerText = '';
+    } else {
+      emulator.pause();
+      showPause('paused');
+    }
+  })
+  document.getElementById('step-into').addEventListener('click', () => {
+    debugCommand(() => showPause(emulator.step_into()));
+  })
+  document.getElementById('step-over').addEventListener('click', () => {
+    debugCommand(() => {
+      const reason = emulator.step_over();
+      if (reason) showPause(reason);
+    });
+  })
+  document.getElementById('step-out').addEventListener('click', () => emulator.step_out())
+  document.getElementById('step-frame').addEventListener('click', () => emulator.step_frame())
+  document.getElementById('breakpoint').addEventListener('change', (e) => {
+    const pc = parseInt(e.target.value, 16);
+    if (Number.isNaN(pc)) return;
+    if (!emulator.remove_breakpoint(pc)) {
+      emulator.add_breakpoint(pc);
+    }
+    e.target.value = '';
+  })
   input.addEventListener("change", (e) => {
+    startAudio(emulator);
     gameState = { ...gameState, romFile: e.target.files[0] }
     readAndLoadFile(gameState.romFile, emulator);
   }, false)
 };
 run();
-function mainLoop(emulator) {
-  if (!gameState.isPaused) {
-    for (let i = 0; i < TICKS_PER_FRAME; i++) {
-      emulator.tick()
-    }
-    if (emulator.tick_timers()) {
-      beep();
+function mainLoop(emulator, timestamp) {
+  const elapsed = lastFrameTime === null ? 0 : timestamp - lastFrameTime;
+  lastFrameTime = timestamp;
+  let redraw = false;
+  let running = false;
+  if (gameState.isRewinding) {
+    emulator.rewind(1);
+    redraw = true;
+  } else if (!gameState.isPaused) {
+    try {
+      const frame = emulator.run_for(elapsed);
+      running = !emulator.is_paused();
+      if (frame.stop) showDebugStatus(emulator, frame.stop);
+      redraw = frame.display_dirty;
+      frame.free();
+    } catch (error) {
+      halt(error);
+      return;
     }
   }
-  ctx.fillStyle = '#994444';
-  ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
-  ctx.fillStyle = 'white';
-  emulator.draw_screen(SCALE);
-  anim_frame = window.requestAnimationFrame(() => mainLoop(emulator));
+  playAudio(emulator, running, elapsed);
+  showRegisters(emulator);
+  if (redraw) {
+    ctx.fillStyle = '#994444';
+    ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
+    emulator.draw_screen(SCALE);
+  }
+  anim_frame = window.requestAnimationFrame((timestamp) => mainLoop(emulator, timestamp));
+}
+// Browsers only allow audio after the user interacted with the page, so the audio graph is set up
+// on the first key press or ROM selection.
+async function startAudio(emulator) {
+  if (audio) return;
+  audio = { ctx: new AudioContext(), node: null };
+  await audio.ctx.audioWorklet.addModule('audio-processor.js');
+  audio.node = new AudioWorkletNode(audio.ctx, 'chip8-audio', { outputChannelCount: [1] });
+  audio.node.connect(audio.ctx.destination);
+  emulator.set_sample_rate(audio.ctx.sampleRate);
+}
+// Sends the samples emulated this frame to the worklet. While the machine is not running it is fed
+// silence for the elapsed time, so a playing tone fades out instead of cutting off.
+function playAudio(emulator, running, elapsed) {
+  if (!audio || !audio.node) return;
+  const minSamples = running ? 0 : Math.round(elapsed * audio.ctx.sampleRate / 1000);
+  const samples = emulator.audio_samples(minSamples);
+  if (samples.length) audio.node.port.postMessage(samples, [samples.buffer]);
+}
+function showRegisters(emulator) {
+  const hex = (value, digits) => value.toString(16).toUpperCase().padStart(digits, '0');
+  const v = Array.from(emulator.registers(), (value, idx) => `V${hex(idx, 1)} ${hex(value, 2)}`);
+  const rows = [];
+  for (let row = 0; row < v.length; row += 4) rows.push(v.slice(row, row + 4).join('  '));
+  rows.push(`I ${hex(emulator.i(), 4)}  PC ${hex(emulator.pc(), 4)}  SP ${emulator.sp()}`);
+  rows.push(`DT ${hex(emulator.delay_timer(), 2)}  ST ${hex(emulator.sound_timer(), 2)}`);
+  rows.push(`Stack ${Array.from(emulator.stack(), addr => hex(addr, 4)).join(' ')}`);
+  registerPanel.innerText = rows.join('\n');
+}
+function showDebugStatus(emulator, reason) {
+  debugStatus.innerText = `Paused (${reason}), next: ${emulator.current_instruction() ?? '?'}`;
+}
+function stateKey(file) {
+  return `emul8tor-state:${file.name}`;
+}
+function variantFor(fileName) {
+  const name = fileName.toLowerCase();
+  if (name.endsWith('.sc8')) return 'schip';
+  if (name.endsWith('.xo8')) return 'xochip';
+  return 'chip8';
 }
-function beep() {
-  snd.play();
+function halt(error) {
+  anim_frame = 0;
+  romName.innerText = `Halted: ${error}`;
+  console.error(error);
 }
\ No newline at end of file